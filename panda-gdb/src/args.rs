@@ -14,6 +14,7 @@ pub struct Args {
     pub on_entry: bool,
     pub on_start: bool,
     pub absolute_addrs: bool,
+    pub kernel: bool,
 }
 
 impl Args {
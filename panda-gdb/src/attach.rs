@@ -0,0 +1,32 @@
+use panda::prelude::*;
+use panda::plugins::osi::OSI;
+
+use crate::target_state::STATE;
+
+/// Make `pid` the debugged process and arrange for the guest to stop the next time
+/// it runs in user mode. Returns the name of the process if it exists.
+pub(crate) fn request_attach(cpu: &mut CPUState, pid: target_ulong) -> Option<String> {
+    let procs = OSI.get_processes(cpu);
+    let proc = procs.iter().find(|proc| proc.pid as target_ulong == pid)?;
+
+    STATE.set_pid(pid);
+    STATE.set_attach_target(proc.asid as _);
+    crate::branch_history::start(proc.asid as _);
+
+    // Already-translated blocks weren't instrumented, so retranslate them so every
+    // instruction gets checked until the process is scheduled
+    unsafe {
+        panda::sys::panda_do_flush_tb();
+    }
+
+    Some(proc.get_name().to_string())
+}
+
+/// Check whether the process waiting to be attached to has just started running in
+/// user mode
+pub(crate) fn reached_attach_target(cpu: &mut CPUState) -> bool {
+    match STATE.attach_target() {
+        Some(asid) => !panda::in_kernel(cpu) && panda::current_asid(cpu) == asid,
+        None => false,
+    }
+}
@@ -0,0 +1,478 @@
+//! An in-memory guest for testing the GDB stub and monitor commands without PANDA.
+//! Every instruction is 4 bytes long and does nothing but advance `rip`.
+
+use panda::prelude::*;
+use panda::regs::Reg;
+
+use gdbstub::common::Tid;
+use gdbstub::target::ext::breakpoints::WatchKind;
+use gdbstub_arch::x86::reg::X86_64CoreRegs;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::net::TcpStream;
+use std::num::NonZeroUsize;
+
+use super::{Backend, Guest, Process, ResumeActions, Thread};
+use crate::branch_history::Branch;
+use crate::breakpoints::{Breakpoint, TraceFrame};
+use crate::memory_map::Mapping;
+use crate::{monitor_commands, process_output};
+use crate::paging::PageWalk;
+use crate::panda_target::Registers;
+use crate::target_state::{self, BreakStatus};
+
+pub(crate) const INSTRUCTION_SIZE: target_ptr_t = 4;
+
+pub(crate) struct Fake {
+    pub regs: X86_64CoreRegs,
+    /// Guest memory, starting at `memory_base`
+    pub memory: Vec<u8>,
+    pub memory_base: target_ptr_t,
+    pub breakpoints: HashSet<target_ptr_t>,
+    pub watchpoints: Vec<(target_ptr_t, WatchKind)>,
+    /// Memory accessed by the instruction at each address, as (addr, size, is_write)
+    pub accesses: HashMap<target_ptr_t, (target_ptr_t, usize, bool)>,
+    /// The process exits once `rip` reaches this address
+    pub exit_at: target_ptr_t,
+    pub exit_code: u8,
+    pub in_kernel: bool,
+    pub processes: Vec<Process>,
+    /// (base, size, file) of each mapping of the current process
+    pub mappings: Vec<(target_ptr_t, target_ptr_t, String)>,
+    /// (base, size, name) of each loaded kernel module
+    pub kernel_modules: Vec<(target_ptr_t, target_ptr_t, String)>,
+    pub memory_taint: HashMap<target_ptr_t, Vec<u32>>,
+    pub register_taint: HashMap<String, Vec<u32>>,
+    /// Open file descriptors of every process, as (pid, fd, path)
+    pub open_files: Vec<(target_ulong, u32, String)>,
+    pub command_lines: HashMap<target_ulong, Vec<String>>,
+    /// What the process writes to its terminal the next time it runs
+    pub output: Vec<u8>,
+    /// GDB's connection, to send `output` to while running
+    pub console: Option<TcpStream>,
+    pub monitor_breakpoints: BTreeMap<target_ptr_t, Breakpoint>,
+    pub trace_frames: Vec<TraceFrame>,
+    pub dropped_trace_frames: usize,
+    /// Taken branches, oldest first
+    pub branches: Vec<Branch>,
+    pub catchpoints: BTreeMap<i32, Option<target_ulong>>,
+    pub switch_catch_asid: Option<target_ulong>,
+    pub last_switch: Option<String>,
+    pub finish_kernel_asid: Option<target_ulong>,
+    pub user_only_stepping: bool,
+    pub memory_view: Option<target_ulong>,
+    pub json_output: bool,
+}
+
+impl Default for Fake {
+    fn default() -> Self {
+        Self {
+            regs: X86_64CoreRegs {
+                rip: 0x1000,
+                ..Default::default()
+            },
+            memory: vec![0; 0x1000],
+            memory_base: 0x1000,
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+            accesses: HashMap::new(),
+            exit_at: 0x2000,
+            exit_code: 0,
+            in_kernel: false,
+            processes: vec![Process {
+                pid: 1234,
+                ppid: 1,
+                asid: 0x1000,
+                create_time: 0,
+                name: String::from("fake_process"),
+            }],
+            mappings: vec![(0x1000, 0x1000, String::from("/bin/fake_process"))],
+            kernel_modules: Vec::new(),
+            memory_taint: HashMap::new(),
+            register_taint: HashMap::new(),
+            open_files: vec![
+                (1234, 0, String::from("/dev/pts/0")),
+                (1234, 1, String::from("/dev/pts/0")),
+            ],
+            command_lines: HashMap::new(),
+            output: Vec::new(),
+            console: None,
+            monitor_breakpoints: BTreeMap::new(),
+            trace_frames: Vec::new(),
+            dropped_trace_frames: 0,
+            branches: Vec::new(),
+            catchpoints: BTreeMap::new(),
+            switch_catch_asid: None,
+            last_switch: None,
+            finish_kernel_asid: None,
+            user_only_stepping: false,
+            memory_view: None,
+            json_output: false,
+        }
+    }
+}
+
+impl Fake {
+    fn thread() -> Tid {
+        NonZeroUsize::new(1).unwrap()
+    }
+
+    fn memory_range(&self, addr: target_ptr_t, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = addr.checked_sub(self.memory_base)? as usize;
+        let end = start.checked_add(len)?;
+
+        if end <= self.memory.len() {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
+}
+
+impl Backend for Fake {
+    fn resume(&mut self, actions: ResumeActions) -> BreakStatus {
+        let output = std::mem::take(&mut self.output);
+        if let Some(console) = self.console.as_mut() {
+            for chunk in output.chunks(8) {
+                console.write_all(process_output::console_packet(chunk).as_bytes()).unwrap();
+            }
+        }
+
+        loop {
+            self.regs.rip += INSTRUCTION_SIZE;
+            let pc = self.regs.rip;
+
+            if pc == self.exit_at {
+                return BreakStatus::Exit(self.exit_code);
+            }
+
+            if self.breakpoints.contains(&pc) {
+                return BreakStatus::Breakpoint(pc);
+            }
+
+            // Stop on the accessing instruction itself, as PANDA does with precise PCs
+            if let Some(&(addr, size, is_write)) = self.accesses.get(&pc) {
+                let hit = target_state::hit_watchpoint(&self.watchpoints, addr, size, is_write);
+                if let Some((kind, watched)) = hit {
+                    return BreakStatus::Watchpoint(kind, watched);
+                }
+            }
+
+            if actions.step {
+                let in_range = actions.step_range
+                    .map(|(start, end)| (start..end).contains(&pc))
+                    .unwrap_or(false);
+
+                if !in_range {
+                    return BreakStatus::StepDone;
+                }
+            }
+        }
+    }
+
+    fn threads(&mut self) -> Vec<Tid> {
+        vec![Self::thread()]
+    }
+
+    fn stopped_thread(&mut self) -> Tid {
+        Self::thread()
+    }
+
+    fn read_registers(&mut self, tid: Tid) -> Option<Registers> {
+        if tid == Self::thread() {
+            Some(self.regs.clone())
+        } else {
+            None
+        }
+    }
+
+    fn write_registers(&mut self, tid: Tid, regs: Registers) -> Option<()> {
+        if tid == Self::thread() {
+            self.regs = regs;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn read_memory(&mut self, _tid: Tid, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
+        let range = self.memory_range(addr, len)?;
+        Some(self.memory[range].to_vec())
+    }
+
+    fn write_memory(&mut self, _tid: Tid, addr: target_ptr_t, data: Vec<u8>) -> Option<()> {
+        let range = self.memory_range(addr, data.len())?;
+        self.memory[range].copy_from_slice(&data);
+
+        Some(())
+    }
+
+    fn add_breakpoint(&mut self, addr: target_ptr_t) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    fn remove_breakpoint(&mut self, addr: target_ptr_t) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    fn add_watchpoint(&mut self, addr: target_ptr_t, kind: WatchKind) -> bool {
+        self.watchpoints.push((addr, kind));
+        true
+    }
+
+    fn remove_watchpoint(&mut self, addr: target_ptr_t, kind: WatchKind) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|&watchpoint| watchpoint != (addr, kind));
+
+        self.watchpoints.len() != len
+    }
+
+    fn section_offsets(&mut self) -> Option<(target_ptr_t, target_ptr_t, Option<target_ptr_t>)> {
+        Some((self.memory_base, self.memory_base, None))
+    }
+
+    fn monitor_command(&mut self, cmd: &str) -> String {
+        let mut output = String::new();
+        monitor_commands::handle_command(cmd, self, &mut output);
+
+        output
+    }
+}
+
+impl Guest for Fake {
+    fn processes(&mut self) -> Vec<Process> {
+        self.processes.clone()
+    }
+
+    fn current_process(&mut self) -> Process {
+        self.processes[0].clone()
+    }
+
+    fn current_thread(&mut self) -> Thread {
+        let pid = self.processes[0].pid;
+
+        Thread { pid, tid: pid }
+    }
+
+    fn in_shared_object(&mut self) -> bool {
+        false
+    }
+
+    fn register(&mut self, reg: Reg) -> Option<u64> {
+        const NAMES: [&str; 16] = [
+            "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp",
+            "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+        ];
+
+        let name = reg.to_string();
+        if name.eq_ignore_ascii_case("rip") {
+            return Some(self.regs.rip);
+        }
+
+        NAMES.iter()
+            .position(|other| other.eq_ignore_ascii_case(&name))
+            .map(|i| self.regs.regs[i])
+    }
+
+    fn mappings(&mut self) -> Vec<Mapping> {
+        self.mappings.iter()
+            .map(|(base, size, file)| Mapping {
+                base: *base,
+                size: *size,
+                modd: 0,
+                name: file.rsplit('/').next().map(String::from),
+                file: Some(file.clone()),
+            })
+            .collect()
+    }
+
+    fn process_mappings(&mut self, pid: target_ulong) -> Option<Vec<Mapping>> {
+        if pid == self.processes[0].pid {
+            Some(self.mappings())
+        } else if self.processes.iter().any(|proc| proc.pid == pid) {
+            Some(Vec::new())
+        } else {
+            None
+        }
+    }
+
+    fn kernel_modules(&mut self) -> Vec<Mapping> {
+        self.kernel_modules.iter()
+            .map(|(base, size, name)| Mapping {
+                base: *base,
+                size: *size,
+                modd: 0,
+                name: Some(name.clone()),
+                file: None,
+            })
+            .collect()
+    }
+
+    fn open_files(&mut self, pid: target_ulong) -> Vec<(u32, String)> {
+        self.open_files.iter()
+            .filter(|(file_pid, _, _)| *file_pid == pid)
+            .map(|(_, fd, path)| (*fd, path.clone()))
+            .collect()
+    }
+
+    fn command_line(&mut self, pid: target_ulong) -> Option<Vec<String>> {
+        self.command_lines.get(&pid).cloned()
+    }
+
+    fn running_threads(&mut self) -> Vec<Thread> {
+        vec![self.current_thread()]
+    }
+
+    fn taint_address(&mut self, addr: target_ptr_t, label: u32) -> target_ptr_t {
+        self.memory_taint.entry(addr).or_default().push(label);
+
+        addr
+    }
+
+    fn taint_register(&mut self, reg: Reg, label: u32) {
+        self.register_taint.entry(reg.to_string()).or_default().push(label);
+    }
+
+    fn address_tainted(&mut self, addr: target_ptr_t) -> bool {
+        self.memory_taint.contains_key(&addr)
+    }
+
+    fn register_tainted(&mut self, reg: Reg) -> bool {
+        self.register_taint.contains_key(&reg.to_string())
+    }
+
+    fn address_labels(&mut self, addr: target_ptr_t) -> Vec<u32> {
+        self.memory_taint.get(&addr).cloned().unwrap_or_default()
+    }
+
+    fn register_labels(&mut self, reg: Reg) -> Vec<u32> {
+        self.register_taint.get(&reg.to_string()).cloned().unwrap_or_default()
+    }
+
+    fn pc(&mut self) -> target_ptr_t {
+        self.regs.rip
+    }
+
+    fn asid(&mut self) -> target_ulong {
+        self.processes[0].asid
+    }
+
+    fn in_kernel(&mut self) -> bool {
+        self.in_kernel
+    }
+
+    fn read_memory(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
+        let range = self.memory_range(addr, len)?;
+
+        Some(self.memory[range].to_vec())
+    }
+
+    fn read_physical(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
+        // Virtual addresses map to the same physical ones
+        Guest::read_memory(self, addr, len)
+    }
+
+    fn virt_to_phys(&mut self, addr: target_ptr_t) -> Option<target_ptr_t> {
+        self.memory_range(addr, 1).map(|_| addr)
+    }
+
+    fn page_walk(&mut self, _addr: target_ptr_t) -> Option<PageWalk> {
+        None
+    }
+
+    fn page_walk_in(&mut self, _asid: target_ulong, _addr: target_ptr_t) -> Option<PageWalk> {
+        None
+    }
+
+    fn attach(&mut self, pid: target_ulong) -> Option<String> {
+        self.processes.iter()
+            .find(|proc| proc.pid == pid)
+            .map(|proc| proc.name.clone())
+    }
+
+    fn flush_translations(&mut self) {}
+
+    fn add_monitor_breakpoint(&mut self, addr: target_ptr_t, breakpoint: Breakpoint) {
+        self.monitor_breakpoints.insert(addr, breakpoint);
+    }
+
+    fn remove_monitor_breakpoint(&mut self, addr: target_ptr_t) -> bool {
+        self.monitor_breakpoints.remove(&addr).is_some()
+    }
+
+    fn for_each_monitor_breakpoint(&mut self, f: &mut dyn FnMut(target_ptr_t, &Breakpoint)) {
+        for (&addr, breakpoint) in &self.monitor_breakpoints {
+            f(addr, breakpoint);
+        }
+    }
+
+    fn with_trace_frames(&mut self, f: &mut dyn FnMut(&[TraceFrame])) {
+        f(&self.trace_frames)
+    }
+
+    fn dropped_trace_frames(&mut self) -> usize {
+        self.dropped_trace_frames
+    }
+
+    fn clear_trace_frames(&mut self) {
+        self.trace_frames.clear();
+        self.dropped_trace_frames = 0;
+    }
+
+    fn branches(&mut self, count: usize) -> Vec<Branch> {
+        self.branches.iter().rev().take(count).copied().collect()
+    }
+
+    fn catchpoints(&mut self) -> Vec<(i32, Option<target_ulong>)> {
+        self.catchpoints.iter().map(|(&exception, &asid)| (exception, asid)).collect()
+    }
+
+    fn add_catchpoint(&mut self, exception: i32, asid: Option<target_ulong>) {
+        self.catchpoints.insert(exception, asid);
+    }
+
+    fn remove_catchpoint(&mut self, exception: i32) -> bool {
+        self.catchpoints.remove(&exception).is_some()
+    }
+
+    fn switch_catch_asid(&mut self) -> Option<target_ulong> {
+        self.switch_catch_asid
+    }
+
+    fn set_switch_catch_asid(&mut self, asid: Option<target_ulong>) {
+        self.switch_catch_asid = asid;
+    }
+
+    fn last_switch(&mut self) -> Option<String> {
+        self.last_switch.clone()
+    }
+
+    fn finish_kernel(&mut self, asid: target_ulong) {
+        self.finish_kernel_asid = Some(asid);
+    }
+
+    fn user_only_stepping(&mut self) -> bool {
+        self.user_only_stepping
+    }
+
+    fn set_user_only_stepping(&mut self, enabled: bool) {
+        self.user_only_stepping = enabled;
+    }
+
+    fn memory_view(&mut self) -> Option<target_ulong> {
+        self.memory_view
+    }
+
+    fn set_memory_view(&mut self, asid: Option<target_ulong>) {
+        self.memory_view = asid;
+    }
+
+    fn json_output(&mut self) -> bool {
+        self.json_output
+    }
+
+    fn set_json_output(&mut self, enabled: bool) {
+        self.json_output = enabled;
+    }
+}
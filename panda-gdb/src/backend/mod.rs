@@ -0,0 +1,192 @@
+//! What the GDB stub and monitor commands need from the emulator, so the same glue
+//! can drive PANDA or, in tests, an in-memory fake
+
+use panda::prelude::*;
+use panda::regs::Reg;
+
+use gdbstub::common::Tid;
+use gdbstub::target::ext::breakpoints::WatchKind;
+
+use crate::branch_history::Branch;
+use crate::breakpoints::{Breakpoint, TraceFrame};
+use crate::memory_map::Mapping;
+use crate::paging::PageWalk;
+use crate::panda_target::Registers;
+use crate::target_state::BreakStatus;
+
+mod panda;
+pub(crate) use self::panda::Panda;
+
+#[cfg(all(test, feature = "x86_64"))]
+pub(crate) mod fake;
+
+/// How GDB asked for the guest to be resumed
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct ResumeActions {
+    pub step: bool,
+    /// The only vCPU allowed to stop after a step, or `None` for any of them
+    pub step_thread: Option<Tid>,
+    /// Keep stepping without stopping while the PC is in `[start, end)`
+    pub step_range: Option<(target_ptr_t, target_ptr_t)>,
+}
+
+/// A guest for the GDB stub to debug
+pub(crate) trait Backend {
+    /// Let the guest run until it stops again, and say why it stopped
+    fn resume(&mut self, actions: ResumeActions) -> BreakStatus;
+
+    /// GDB threads of every vCPU
+    fn threads(&mut self) -> Vec<Tid>;
+
+    /// The GDB thread of the vCPU that last stopped
+    fn stopped_thread(&mut self) -> Tid;
+
+    fn read_registers(&mut self, tid: Tid) -> Option<Registers>;
+    fn write_registers(&mut self, tid: Tid, regs: Registers) -> Option<()>;
+
+    fn read_memory(&mut self, tid: Tid, addr: target_ptr_t, len: usize) -> Option<Vec<u8>>;
+    fn write_memory(&mut self, tid: Tid, addr: target_ptr_t, data: Vec<u8>) -> Option<()>;
+
+    fn add_breakpoint(&mut self, addr: target_ptr_t) -> bool;
+    fn remove_breakpoint(&mut self, addr: target_ptr_t) -> bool;
+
+    fn add_watchpoint(&mut self, addr: target_ptr_t, kind: WatchKind) -> bool;
+    fn remove_watchpoint(&mut self, addr: target_ptr_t, kind: WatchKind) -> bool;
+
+    /// Load addresses of the text, data and bss sections of the debugged binary
+    fn section_offsets(&mut self) -> Option<(target_ptr_t, target_ptr_t, Option<target_ptr_t>)>;
+
+    /// Run a monitor command and collect its output
+    fn monitor_command(&mut self, cmd: &str) -> String;
+}
+
+/// A guest process, as seen through OSI
+#[derive(Clone)]
+pub(crate) struct Process {
+    pub pid: target_ulong,
+    pub ppid: target_ulong,
+    pub asid: target_ulong,
+    pub create_time: u64,
+    pub name: String,
+}
+
+/// A guest thread, as seen through OSI
+#[derive(Clone)]
+pub(crate) struct Thread {
+    pub pid: target_ulong,
+    pub tid: target_ulong,
+}
+
+/// What monitor commands can ask of a stopped guest
+pub(crate) trait Guest {
+    fn processes(&mut self) -> Vec<Process>;
+    fn current_process(&mut self) -> Process;
+    fn current_thread(&mut self) -> Thread;
+
+    /// Whether the PC is in a shared library of the current process
+    fn in_shared_object(&mut self) -> bool;
+
+    /// Value of a register of the stopped vCPU
+    fn register(&mut self, reg: Reg) -> Option<u64>;
+
+    /// Memory mappings of the current process
+    fn mappings(&mut self) -> Vec<Mapping>;
+
+    /// Memory mappings of any process, or `None` if there's no such process
+    fn process_mappings(&mut self, pid: target_ulong) -> Option<Vec<Mapping>>;
+
+    /// Kernel modules loaded in the guest
+    fn kernel_modules(&mut self) -> Vec<Mapping>;
+
+    /// Open file descriptors of a process along with the path each one refers to
+    fn open_files(&mut self, pid: target_ulong) -> Vec<(u32, String)>;
+
+    /// Arguments a process was started with, if they were seen
+    fn command_line(&mut self, pid: target_ulong) -> Option<Vec<String>>;
+
+    /// Threads currently running on each vCPU
+    fn running_threads(&mut self) -> Vec<Thread>;
+
+    /// Taint the memory at a virtual address, returning the physical address tainted
+    fn taint_address(&mut self, addr: target_ptr_t, label: u32) -> target_ptr_t;
+    fn taint_register(&mut self, reg: Reg, label: u32);
+    fn address_tainted(&mut self, addr: target_ptr_t) -> bool;
+    fn register_tainted(&mut self, reg: Reg) -> bool;
+    /// Taint labels on a virtual address, empty if it isn't tainted
+    fn address_labels(&mut self, addr: target_ptr_t) -> Vec<u32>;
+    /// Taint labels on a register, empty if it isn't tainted
+    fn register_labels(&mut self, reg: Reg) -> Vec<u32>;
+
+    /// PC of the stopped vCPU
+    fn pc(&mut self) -> target_ptr_t;
+
+    /// ASID of the stopped vCPU
+    fn asid(&mut self) -> target_ulong;
+
+    /// Whether the stopped vCPU is running kernel code
+    fn in_kernel(&mut self) -> bool;
+
+    /// Read virtual memory of the current process
+    fn read_memory(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>>;
+
+    /// Read guest physical memory
+    fn read_physical(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>>;
+
+    /// Translate a virtual address of the current process, or `None` if it isn't mapped
+    fn virt_to_phys(&mut self, addr: target_ptr_t) -> Option<target_ptr_t>;
+
+    /// Walk the page tables for a virtual address, or `None` if the architecture isn't
+    /// supported
+    fn page_walk(&mut self, addr: target_ptr_t) -> Option<PageWalk>;
+
+    /// Walk the page tables of another address space, like `page_walk`
+    fn page_walk_in(&mut self, asid: target_ulong, addr: target_ptr_t) -> Option<PageWalk>;
+
+    /// Make `pid` the debugged process, stopping in it once it's next scheduled.
+    /// Returns the name of the process if it exists.
+    fn attach(&mut self, pid: target_ulong) -> Option<String>;
+
+    /// Throw away translated code, so code with a new breakpoint gets checked for it
+    fn flush_translations(&mut self);
+
+    /// Add a breakpoint set through the monitor, replacing any at the same address
+    fn add_monitor_breakpoint(&mut self, addr: target_ptr_t, breakpoint: Breakpoint);
+    fn remove_monitor_breakpoint(&mut self, addr: target_ptr_t) -> bool;
+    /// Run `f` on each monitor breakpoint in address order
+    fn for_each_monitor_breakpoint(&mut self, f: &mut dyn FnMut(target_ptr_t, &Breakpoint));
+
+    /// Run `f` on the trace frames collected so far, oldest first
+    fn with_trace_frames(&mut self, f: &mut dyn FnMut(&[TraceFrame]));
+    /// How many trace frames weren't kept because the buffer was full
+    fn dropped_trace_frames(&mut self) -> usize;
+    fn clear_trace_frames(&mut self);
+
+    /// Up to `count` of the branches taken by the debugged process, most recent first
+    fn branches(&mut self, count: usize) -> Vec<Branch>;
+
+    /// Exceptions being caught, each with the only ASID it's caught in if there is one
+    fn catchpoints(&mut self) -> Vec<(i32, Option<target_ulong>)>;
+    fn add_catchpoint(&mut self, exception: i32, asid: Option<target_ulong>);
+    fn remove_catchpoint(&mut self, exception: i32) -> bool;
+
+    /// The ASID to stop on context switches into or out of, if enabled
+    fn switch_catch_asid(&mut self) -> Option<target_ulong>;
+    fn set_switch_catch_asid(&mut self, asid: Option<target_ulong>);
+    /// Description of the last context switch stopped on
+    fn last_switch(&mut self) -> Option<String>;
+
+    /// Keep running until `asid` returns to user mode
+    fn finish_kernel(&mut self, asid: target_ulong);
+
+    /// Whether steps from user mode skip over kernel code
+    fn user_only_stepping(&mut self) -> bool;
+    fn set_user_only_stepping(&mut self, enabled: bool);
+
+    /// The ASID whose address space memory reads go through, if not the current one
+    fn memory_view(&mut self) -> Option<target_ulong>;
+    fn set_memory_view(&mut self, asid: Option<target_ulong>);
+
+    /// Whether commands print JSON without being given `--json`
+    fn json_output(&mut self) -> bool;
+    fn set_json_output(&mut self, enabled: bool);
+}
@@ -0,0 +1,591 @@
+use panda::prelude::*;
+use panda::plugins::osi::{OSI, OsiProc};
+use panda::regs::{self, Reg};
+use panda::taint;
+
+use gdbstub::common::Tid;
+use gdbstub::target::ext::breakpoints::WatchKind;
+
+use std::convert::TryInto;
+use std::os::raw::c_int;
+
+use super::{Backend, Guest, Process, ResumeActions, Thread};
+use crate::branch_history::Branch;
+use crate::breakpoints::{Breakpoint, TraceFrame};
+use crate::memory_map::{self, Mapping};
+use crate::panda_target::Registers;
+use crate::target_state::{self, BreakStatus, STATE};
+use crate::paging::{self, PageWalk};
+use crate::{attach, file_descriptors, monitor_commands};
+
+#[cfg(feature = "x86_64")]
+use gdbstub_arch::x86::reg::{X86_64CoreRegs, X86SegmentRegs, F80};
+
+#[cfg(feature = "i386")]
+use gdbstub_arch::x86::reg::{X86CoreRegs, X86SegmentRegs, F80};
+
+#[cfg(feature = "arm")]
+use gdbstub_arch::arm::reg::ArmCoreRegs;
+
+/// Descriptors checked by `procinfo`, since OSI can't list the open ones directly or
+/// say how big a process's descriptor table is
+const MAX_FDS: c_int = 256;
+
+/// The real guest. Everything that touches the CPU runs on the PANDA thread through
+/// `STATE`, while the guest is stopped.
+pub(crate) struct Panda;
+
+impl Backend for Panda {
+    fn resume(&mut self, actions: ResumeActions) -> BreakStatus {
+        STATE.set_step_thread(actions.step_thread);
+        match actions.step_range {
+            Some((start, end)) => STATE.set_step_range(start, end),
+            None => STATE.clear_step_range(),
+        }
+
+        if actions.step {
+            STATE.start_single_stepping();
+        }
+
+        // Stepping into a syscall or interrupt from user mode shouldn't stop in the kernel
+        let stepping = STATE.single_stepping();
+        if stepping && STATE.user_only_stepping() && STATE.step_return_asid().is_none() {
+            let tid = STATE.step_thread().unwrap_or_else(|| STATE.stopped_thread());
+            STATE.with_thread(tid, |cpu| {
+                if !panda::in_kernel(cpu) {
+                    STATE.set_step_return_asid(panda::current_asid(cpu));
+                }
+            });
+        }
+
+        STATE.resume();
+        STATE.brk.wait_for()
+    }
+
+    fn threads(&mut self) -> Vec<Tid> {
+        target_state::threads()
+    }
+
+    fn stopped_thread(&mut self) -> Tid {
+        STATE.stopped_thread()
+    }
+
+    fn read_registers(&mut self, tid: Tid) -> Option<Registers> {
+        STATE.with_thread(tid, |cpu| {
+            let mut regs: Registers = Default::default();
+            read_cpu_registers(cpu, &mut regs);
+            regs
+        })
+    }
+
+    fn write_registers(&mut self, tid: Tid, regs: Registers) -> Option<()> {
+        STATE.with_thread(tid, move |cpu| write_cpu_registers(cpu, &regs))
+    }
+
+    fn read_memory(&mut self, tid: Tid, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
+        STATE.with_thread(tid, move |cpu| match STATE.get_memory_view() {
+            Some(asid) => paging::read_in(cpu, asid, addr, len),
+            None => cpu.try_mem_read(addr, len),
+        }).flatten()
+    }
+
+    fn write_memory(&mut self, tid: Tid, addr: target_ptr_t, data: Vec<u8>) -> Option<()> {
+        // Writes always go to the current process, so refuse them rather than
+        // writing somewhere other than what `x` is showing
+        if STATE.get_memory_view().is_some() {
+            return None;
+        }
+
+        STATE.with_thread(tid, move |cpu| cpu.mem_write(addr, &data))
+    }
+
+    fn add_breakpoint(&mut self, addr: target_ptr_t) -> bool {
+        STATE.add_breakpoint(addr)
+    }
+
+    fn remove_breakpoint(&mut self, addr: target_ptr_t) -> bool {
+        STATE.remove_breakpoint(addr)
+    }
+
+    fn add_watchpoint(&mut self, addr: target_ptr_t, kind: WatchKind) -> bool {
+        STATE.add_watchpoint(addr, kind);
+
+        // Without precise PCs, the PC memory callbacks get is only updated per block
+        unsafe {
+            panda::sys::panda_enable_memcb();
+            panda::sys::panda_enable_precise_pc();
+        }
+
+        true
+    }
+
+    fn remove_watchpoint(&mut self, addr: target_ptr_t, kind: WatchKind) -> bool {
+        // Memory callbacks stay on: other plugins (such as taint2) may rely on them too, and
+        // there's no way to tell whether they were on before the first watchpoint
+        STATE.remove_watchpoint(addr, kind)
+    }
+
+    fn section_offsets(&mut self) -> Option<(target_ptr_t, target_ptr_t, Option<target_ptr_t>)> {
+        // The kernel image runs at its link address, so it doesn't need relocating
+        if crate::ARGS.kernel {
+            return Some((0, 0, None));
+        }
+
+        STATE.with_cpu(|cpu| {
+            let mut process = OSI.get_current_process(cpu);
+            let mappings = OSI.get_mappings(cpu, &mut *process);
+            if mappings.len() >= 3 {
+                Some((mappings[0].base, mappings[1].base, Some(mappings[2].base)))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn monitor_command(&mut self, cmd: &str) -> String {
+        let cmd = cmd.to_owned();
+        STATE.with_cpu(move |cpu| {
+            let mut output = String::new();
+            monitor_commands::handle_command(&cmd, &mut PandaGuest { cpu }, &mut output);
+            output
+        })
+    }
+}
+
+/// The stopped guest, for monitor commands running on the PANDA thread
+pub(crate) struct PandaGuest<'a> {
+    pub cpu: &'a mut CPUState,
+}
+
+impl Guest for PandaGuest<'_> {
+    fn processes(&mut self) -> Vec<Process> {
+        OSI.get_processes(self.cpu)
+            .iter()
+            .map(|proc| Process {
+                pid: proc.pid as _,
+                ppid: proc.ppid as _,
+                asid: proc.asid as _,
+                create_time: proc.create_time as _,
+                name: proc.get_name().to_string(),
+            })
+            .collect()
+    }
+
+    fn current_process(&mut self) -> Process {
+        let proc = OSI.get_current_process(self.cpu);
+
+        Process {
+            pid: proc.pid as _,
+            ppid: proc.ppid as _,
+            asid: proc.asid as _,
+            create_time: proc.create_time as _,
+            name: proc.get_name().to_string(),
+        }
+    }
+
+    fn current_thread(&mut self) -> Thread {
+        let thread = OSI.get_current_thread(self.cpu);
+
+        Thread {
+            pid: thread.pid as _,
+            tid: thread.tid as _,
+        }
+    }
+
+    fn in_shared_object(&mut self) -> bool {
+        let proc = OSI.get_current_process(self.cpu);
+        OSI.in_shared_object(self.cpu, &*proc)
+    }
+
+    fn register(&mut self, reg: Reg) -> Option<u64> {
+        Some(regs::get_reg(self.cpu, reg) as u64)
+    }
+
+    fn mappings(&mut self) -> Vec<Mapping> {
+        memory_map::get(self.cpu)
+    }
+
+    fn process_mappings(&mut self, pid: target_ulong) -> Option<Vec<Mapping>> {
+        self.with_process(pid, |cpu, proc| memory_map::get_for(cpu, proc))
+    }
+
+    fn kernel_modules(&mut self) -> Vec<Mapping> {
+        memory_map::kernel_modules(self.cpu)
+    }
+
+    fn open_files(&mut self, pid: target_ulong) -> Vec<(u32, String)> {
+        self.with_process(pid, |cpu, proc| {
+            (0..MAX_FDS)
+                .filter_map(|fd| file_descriptors::path(cpu, proc, fd).map(|file| (fd as u32, file)))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    fn command_line(&mut self, pid: target_ulong) -> Option<Vec<String>> {
+        STATE.command_line(pid)
+    }
+
+    fn running_threads(&mut self) -> Vec<Thread> {
+        target_state::guest_cpus()
+            .into_iter()
+            .map(|cpu| {
+                // SAFETY: the vCPUs outlive the plugin and are all paused while stopped
+                let thread = OSI.get_current_thread(unsafe { &mut *cpu });
+
+                Thread {
+                    pid: thread.pid as _,
+                    tid: thread.tid as _,
+                }
+            })
+            .collect()
+    }
+
+    fn taint_address(&mut self, addr: target_ptr_t, label: u32) -> target_ptr_t {
+        let addr = panda::mem::virt_to_phys(self.cpu, addr);
+        taint::label_ram(addr, label);
+
+        addr
+    }
+
+    fn taint_register(&mut self, reg: Reg, label: u32) {
+        taint::label_reg(reg, label);
+    }
+
+    fn address_tainted(&mut self, addr: target_ptr_t) -> bool {
+        taint::check_ram(panda::mem::virt_to_phys(self.cpu, addr))
+    }
+
+    fn register_tainted(&mut self, reg: Reg) -> bool {
+        taint::check_reg(reg)
+    }
+
+    fn address_labels(&mut self, addr: target_ptr_t) -> Vec<u32> {
+        let addr = panda::mem::virt_to_phys(self.cpu, addr);
+
+        // TODO: fix segfault
+        if taint::check_ram(addr) {
+            taint::get_ram(addr).into_iter().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn register_labels(&mut self, reg: Reg) -> Vec<u32> {
+        // TODO: fix segfault
+        if taint::check_reg(reg) {
+            taint::get_reg(reg).into_iter().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn pc(&mut self) -> target_ptr_t {
+        panda::current_pc(self.cpu)
+    }
+
+    fn asid(&mut self) -> target_ulong {
+        panda::current_asid(self.cpu)
+    }
+
+    fn in_kernel(&mut self) -> bool {
+        panda::in_kernel(self.cpu)
+    }
+
+    fn read_memory(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
+        self.cpu.try_mem_read(addr, len)
+    }
+
+    fn read_physical(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
+        panda::mem::physical_memory_read(addr as _, len).ok()
+    }
+
+    fn virt_to_phys(&mut self, addr: target_ptr_t) -> Option<target_ptr_t> {
+        // PANDA returns -1 when the address has no translation
+        match panda::mem::virt_to_phys(self.cpu, addr) {
+            phys if phys == target_ptr_t::MAX => None,
+            phys => Some(phys),
+        }
+    }
+
+    fn page_walk(&mut self, addr: target_ptr_t) -> Option<PageWalk> {
+        paging::walk(self.cpu, addr)
+    }
+
+    fn page_walk_in(&mut self, asid: target_ulong, addr: target_ptr_t) -> Option<PageWalk> {
+        paging::walk_in(self.cpu, asid as _, addr)
+    }
+
+    fn attach(&mut self, pid: target_ulong) -> Option<String> {
+        attach::request_attach(self.cpu, pid)
+    }
+
+    fn flush_translations(&mut self) {
+        unsafe {
+            panda::sys::panda_do_flush_tb();
+        }
+    }
+
+    fn add_monitor_breakpoint(&mut self, addr: target_ptr_t, breakpoint: Breakpoint) {
+        STATE.add_monitor_breakpoint(addr, breakpoint);
+    }
+
+    fn remove_monitor_breakpoint(&mut self, addr: target_ptr_t) -> bool {
+        STATE.remove_monitor_breakpoint(addr)
+    }
+
+    fn for_each_monitor_breakpoint(&mut self, f: &mut dyn FnMut(target_ptr_t, &Breakpoint)) {
+        STATE.for_each_monitor_breakpoint(f);
+    }
+
+    fn with_trace_frames(&mut self, f: &mut dyn FnMut(&[TraceFrame])) {
+        STATE.with_trace_frames(f);
+    }
+
+    fn dropped_trace_frames(&mut self) -> usize {
+        STATE.dropped_trace_frames()
+    }
+
+    fn clear_trace_frames(&mut self) {
+        STATE.clear_trace_frames();
+    }
+
+    fn branches(&mut self, count: usize) -> Vec<Branch> {
+        STATE.with_branch_history(|history| history.latest(count))
+    }
+
+    fn catchpoints(&mut self) -> Vec<(i32, Option<target_ulong>)> {
+        STATE.catchpoints()
+    }
+
+    fn add_catchpoint(&mut self, exception: i32, asid: Option<target_ulong>) {
+        STATE.add_catchpoint(exception, asid);
+    }
+
+    fn remove_catchpoint(&mut self, exception: i32) -> bool {
+        STATE.remove_catchpoint(exception)
+    }
+
+    fn switch_catch_asid(&mut self) -> Option<target_ulong> {
+        STATE.switch_catch_asid()
+    }
+
+    fn set_switch_catch_asid(&mut self, asid: Option<target_ulong>) {
+        match asid {
+            Some(asid) => STATE.catch_switches(asid),
+            None => STATE.stop_catching_switches(),
+        }
+    }
+
+    fn last_switch(&mut self) -> Option<String> {
+        STATE.last_switch()
+    }
+
+    fn finish_kernel(&mut self, asid: target_ulong) {
+        STATE.set_finish_kernel(asid);
+    }
+
+    fn user_only_stepping(&mut self) -> bool {
+        STATE.user_only_stepping()
+    }
+
+    fn set_user_only_stepping(&mut self, enabled: bool) {
+        STATE.set_user_only_stepping(enabled);
+    }
+
+    fn memory_view(&mut self) -> Option<target_ulong> {
+        STATE.get_memory_view()
+    }
+
+    fn set_memory_view(&mut self, asid: Option<target_ulong>) {
+        match asid {
+            Some(asid) => STATE.set_memory_view(asid),
+            None => STATE.reset_memory_view(),
+        }
+    }
+
+    fn json_output(&mut self) -> bool {
+        STATE.json_output()
+    }
+
+    fn set_json_output(&mut self, enabled: bool) {
+        STATE.set_json_output(enabled);
+    }
+}
+
+impl PandaGuest<'_> {
+    /// Pass the OSI process with this PID to `f`, for OSI functions taking a process
+    fn with_process<T>(
+        &mut self,
+        pid: target_ulong,
+        f: impl FnOnce(&mut CPUState, &mut OsiProc) -> T,
+    ) -> Option<T> {
+        let mut procs = OSI.get_processes(self.cpu);
+        let proc = procs.iter_mut().find(|proc| proc.pid as target_ulong == pid)?;
+
+        Some(f(self.cpu, proc))
+    }
+}
+
+/// Read the registers of a vCPU, on the PANDA thread
+fn read_cpu_registers(cpu: &mut CPUState, regs: &mut Registers) {
+    let pc = STATE.pc_of(cpu);
+
+    #[cfg(feature = "x86_64")] {
+        let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUX86State) };
+
+        let segments: [u32; 6] = (&env.segs.iter().map(|seg| seg.base as u32)
+            .collect::<Vec<_>>()[..6])
+            .try_into()
+            .unwrap();
+
+        let segments = X86SegmentRegs {
+            cs: segments[0],
+            ss: segments[1],
+            ds: segments[2],
+            es: segments[3],
+            fs: segments[4],
+            gs: segments[5],
+        };
+
+        *regs = X86_64CoreRegs {
+            eflags: env.eflags as _,
+            regs: (*env).regs.clone(),
+            rip: pc,
+            segments,
+            st: (&env.fpregs.iter().map(fpreg_to_bytes).collect::<Vec<_>>()[..8]).try_into().unwrap(),
+            xmm: (&env.xmm_regs.iter().map(zmm_to_xmm).collect::<Vec<_>>()[..16]).try_into().unwrap(),
+            mxcsr: env.mxcsr,
+            ..Default::default()
+        };
+    }
+    
+    #[cfg(feature = "i386")] {
+        let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUX86State) };
+
+        use panda::sys::{R_EAX, R_EBX, R_ECX, R_EDX, R_ESP, R_EBP, R_ESI, R_EDI};
+
+        let segments: [u32; 6] = (&env.segs.iter().map(|seg| seg.base as u32)
+            .collect::<Vec<_>>()[..6])
+            .try_into()
+            .unwrap();
+
+        let segments = X86SegmentRegs {
+            cs: segments[0],
+            ss: segments[1],
+            ds: segments[2],
+            es: segments[3],
+            fs: segments[4],
+            gs: segments[5],
+        };
+
+        *regs = X86CoreRegs {
+            eflags: env.eflags,
+            eax: env.regs[R_EAX as usize],
+            ebx: env.regs[R_EBX as usize],
+            ecx: env.regs[R_ECX as usize],
+            edx: env.regs[R_EDX as usize],
+            esp: env.regs[R_ESP as usize],
+            ebp: env.regs[R_EBP as usize],
+            esi: env.regs[R_ESI as usize],
+            edi: env.regs[R_EDI as usize],
+            eip: pc,
+            segments,
+            st: (&(*env).fpregs.iter().map(fpreg_to_bytes).collect::<Vec<_>>()[..8]).try_into().unwrap(),
+            xmm: (&(*env).xmm_regs.iter().map(zmm_to_xmm).collect::<Vec<_>>()[..8]).try_into().unwrap(),
+            mxcsr: (*env).mxcsr,
+            ..Default::default()
+        };
+    }
+    
+    #[cfg(feature = "arm")] {
+        let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUARMState) };
+
+        *regs = ArmCoreRegs {
+            r: env.regs[0..13].try_into().unwrap(),
+            sp: env.regs[13],
+            lr: env.regs[14],
+            pc,
+            cpsr: env.uncached_cpsr,
+            ..Default::default()
+        };
+    }
+
+    #[cfg(feature = "ppc")] {
+
+    }
+    
+    #[cfg(any(feature = "mips", feature = "mipsel"))] {
+        let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUMIPSState) };
+
+        regs.r = env.active_tc.gpr;
+        regs.lo = env.active_tc.LO[0];
+        regs.hi = env.active_tc.HI[0];
+        regs.pc = pc;
+        regs.cp0.status = env.CP0_Status as _;
+        regs.cp0.badvaddr = env.CP0_BadVAddr as _;
+        regs.cp0.cause = env.CP0_Cause as _;
+        // TODO: fpu
+    }
+}
+
+/// Write the registers of a vCPU, on the PANDA thread
+fn write_cpu_registers(cpu: &mut CPUState, regs: &Registers) {
+    #[cfg(feature = "x86_64")] {
+        let env = cpu.env_ptr as *mut panda::sys::CPUX86State;
+
+        unsafe {
+            (*env).regs = regs.regs.clone();
+            (*env).eip = regs.rip;
+            (*env).mxcsr = regs.mxcsr;
+        }
+    }
+    #[cfg(feature = "arm")] {
+        let env = unsafe { &mut *(cpu.env_ptr as *mut panda::sys::CPUARMState) };
+        
+        for i in 0..13 {
+            env.regs[i] = regs.r[i];
+        }
+        env.regs[13] = regs.sp;
+        env.regs[14] = regs.lr;
+        env.regs[15] = regs.pc;
+        env.uncached_cpsr = regs.cpsr;
+    }
+    #[cfg(feature = "i386")] {
+        let env = unsafe { &mut *(cpu.env_ptr as *mut panda::sys::CPUX86State) };
+
+        use panda::sys::{R_EAX, R_EBX, R_ECX, R_EDX, R_ESP, R_EBP, R_ESI, R_EDI};
+
+        for &(i, val) in &[
+            (R_EAX, regs.eax),
+            (R_EBX, regs.ebx),
+            (R_ECX, regs.ecx),
+            (R_EDX, regs.edx),
+            (R_ESP, regs.esp),
+            (R_EBP, regs.ebp),
+            (R_ESI, regs.esi),
+            (R_EDI, regs.edi),
+        ] {
+            env.regs[i as usize] = val;
+        }
+
+        env.eflags = regs.eflags;
+        if target_state::thread_id(cpu) == STATE.stopped_thread() {
+            STATE.set_pc(regs.eip);
+        }
+    }
+}
+
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+fn fpreg_to_bytes(x: &panda::sys::FPReg) -> F80 {
+    unsafe {
+        std::mem::transmute_copy(x)
+    }
+}
+
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+fn zmm_to_xmm(x: &panda::sys::ZMMReg) -> u128 {
+    unsafe {
+        std::mem::transmute_copy(x)
+    }
+}
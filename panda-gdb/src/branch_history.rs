@@ -0,0 +1,159 @@
+//! The last branches taken by the debugged process, kept like a CPU's last branch
+//! record so it's possible to tell how it got somewhere once the stack is no help
+
+use panda::prelude::*;
+
+use std::collections::VecDeque;
+
+use crate::target_state::STATE;
+
+/// How many branches are remembered
+pub(crate) const CAPACITY: usize = 1024;
+
+/// A taken branch. The instruction that branched isn't known, only the block that
+/// ended with it.
+#[derive(Clone, Copy)]
+pub(crate) struct Branch {
+    pub block_start: target_ptr_t,
+    /// Address just past the end of the block
+    pub block_end: target_ptr_t,
+    pub target: target_ptr_t,
+}
+
+pub(crate) struct BranchHistory {
+    /// Address space of the debugged process, if there is one
+    asid: Option<target_ulong>,
+    /// Start and end of the last block completed in user mode by the debugged process
+    last_block: Option<(target_ptr_t, target_ptr_t)>,
+    branches: VecDeque<Branch>,
+}
+
+impl BranchHistory {
+    pub(crate) fn new() -> Self {
+        BranchHistory {
+            asid: None,
+            last_block: None,
+            branches: VecDeque::with_capacity(CAPACITY),
+        }
+    }
+
+    /// Record the blocks run in a new address space, forgetting the branches before
+    pub(crate) fn start(&mut self, asid: target_ulong) {
+        self.asid = Some(asid);
+        self.last_block = None;
+        self.branches.clear();
+    }
+
+    pub(crate) fn record_block(&mut self, asid: target_ulong, start: target_ptr_t, end: target_ptr_t) {
+        if self.asid != Some(asid) {
+            return
+        }
+
+        // Anything but falling through from the last block means it branched here
+        if let Some((last_start, last_end)) = self.last_block {
+            if start != last_end {
+                if self.branches.len() == CAPACITY {
+                    self.branches.pop_front();
+                }
+
+                self.branches.push_back(Branch {
+                    block_start: last_start,
+                    block_end: last_end,
+                    target: start,
+                });
+            }
+        }
+
+        self.last_block = Some((start, end));
+    }
+
+    /// Up to `count` of the branches taken, most recent first
+    pub(crate) fn latest(&self, count: usize) -> Vec<Branch> {
+        self.branches.iter().rev().take(count).copied().collect()
+    }
+}
+
+/// Start recording the branches of a newly debugged process
+pub(crate) fn start(asid: target_ulong) {
+    STATE.with_branch_history(|history| history.start(asid));
+
+    // Chained blocks jump straight into each other without `after_block_exec`, so
+    // stop chaining and throw away the links made so far
+    unsafe {
+        panda::sys::panda_disable_tb_chaining();
+        panda::sys::panda_do_flush_tb();
+    }
+}
+
+/// Stop recording once the process is no longer being debugged
+pub(crate) fn stop() {
+    unsafe {
+        panda::sys::panda_enable_tb_chaining();
+    }
+}
+
+/// Called after every block that runs to its end. Kernel code and other processes are left out, so
+/// a system call or context switch doesn't show up as a branch.
+pub(crate) fn record(cpu: &mut CPUState, tb: &TranslationBlock) {
+    if !STATE.is_pid_set() || panda::in_kernel(cpu) {
+        return
+    }
+
+    let start = tb.pc as target_ptr_t;
+    let end = start + tb.size as target_ptr_t;
+    let asid = panda::current_asid(cpu);
+
+    STATE.with_branch_history(|history| history.record_block(asid, start, end));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_history_fall_through() {
+        let mut history = BranchHistory::new();
+        history.start(0x1000);
+
+        history.record_block(0x1000, 0x400000, 0x400010);
+        history.record_block(0x1000, 0x400010, 0x400020);
+        assert!(history.latest(16).is_empty());
+    }
+
+    #[test]
+    fn branch_history_taken_branch() {
+        let mut history = BranchHistory::new();
+        history.start(0x1000);
+
+        history.record_block(0x1000, 0x400000, 0x400010);
+        history.record_block(0x1000, 0x400100, 0x400108);
+        // Blocks of other processes are ignored
+        history.record_block(0x2000, 0x500000, 0x500010);
+
+        let branches = history.latest(16);
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].block_start, 0x400000);
+        assert_eq!(branches[0].block_end, 0x400010);
+        assert_eq!(branches[0].target, 0x400100);
+    }
+
+    #[test]
+    fn branch_history_full() {
+        let mut history = BranchHistory::new();
+        history.start(0x1000);
+
+        // Jump back and forth between two blocks, one more time than is remembered
+        history.record_block(0x1000, 0x400000, 0x400010);
+        for i in 0..=CAPACITY {
+            let start = if i % 2 == 0 { 0x400100 } else { 0x400000 };
+            history.record_block(0x1000, start, start + 0x10);
+        }
+
+        let branches = history.latest(usize::MAX);
+        assert_eq!(branches.len(), CAPACITY);
+
+        // The last jump went to 0x400100, and the first one is forgotten
+        assert_eq!(branches[0].target, 0x400100);
+        assert_eq!(branches[CAPACITY - 1].target, 0x400000);
+    }
+}
@@ -0,0 +1,254 @@
+use panda::prelude::*;
+use panda::regs::{self, Reg};
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::memory_map;
+use crate::target_state::STATE;
+
+/// A breakpoint added through the monitor rather than by GDB
+pub(crate) struct Breakpoint {
+    pub condition: Option<Condition>,
+    /// If set, hits are logged and the guest keeps running
+    pub log: Option<LogPoint>,
+    hits: AtomicU64,
+}
+
+/// What a log point records on each hit, and where to
+pub(crate) struct LogPoint {
+    pub items: Vec<LogItem>,
+    pub output: LogOutput,
+}
+
+pub(crate) enum LogOutput {
+    /// PANDA's stdout
+    Stdout,
+    File { path: String, file: Mutex<File> },
+    /// Kept in memory as trace frames, for `tfind` and `tsave`
+    Trace,
+}
+
+/// Most trace frames kept at once, so tracing a hot address can't use up host memory
+pub(crate) const MAX_TRACE_FRAMES: usize = 100_000;
+
+/// The values collected by one hit of a tracepoint
+pub(crate) struct TraceFrame {
+    pub pc: target_ptr_t,
+    pub instr_count: u64,
+    pub hit: u64,
+    /// Each collected item along with its value, or `None` if it couldn't be read
+    pub values: Vec<(String, Option<String>)>,
+}
+
+pub(crate) enum LogItem {
+    Value(Operand),
+    /// `len` bytes of memory, printed as hex
+    Bytes(Operand, usize),
+}
+
+impl Breakpoint {
+    pub(crate) fn new(condition: Option<Condition>, log: Option<LogPoint>) -> Self {
+        Self { condition, log, hits: AtomicU64::new(0) }
+    }
+
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    /// Count a hit if the condition holds and return whether the guest should stop.
+    /// Evaluated on the PANDA thread, so a false condition or a log point costs no
+    /// round trip to GDB.
+    pub(crate) fn should_stop(&self, cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+        if let Some(condition) = &self.condition {
+            if !condition.eval(cpu) {
+                return false;
+            }
+        }
+
+        let hits = self.hits.fetch_add(1, Ordering::SeqCst) + 1;
+        match &self.log {
+            Some(log) => {
+                log.record(cpu, pc, hits);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl LogPoint {
+    pub(crate) fn new(items: Vec<LogItem>, path: Option<String>) -> std::io::Result<Self> {
+        let output = match path {
+            Some(path) => {
+                let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+                LogOutput::File { path, file: Mutex::new(file) }
+            }
+            None => LogOutput::Stdout,
+        };
+
+        Ok(Self { items, output })
+    }
+
+    pub(crate) fn trace(items: Vec<LogItem>) -> Self {
+        Self { items, output: LogOutput::Trace }
+    }
+
+    fn record(&self, cpu: &mut CPUState, pc: target_ptr_t, hit: u64) {
+        let instr_count = unsafe { panda::sys::rr_get_guest_instr_count_external() };
+        let values = self.items.iter()
+            .map(|item| {
+                let value = match item {
+                    LogItem::Value(operand) => operand.eval(cpu).map(|value| format!("{:#x}", value)),
+                    LogItem::Bytes(addr, len) => addr.eval(cpu)
+                        .and_then(|addr| cpu.try_mem_read(addr as target_ptr_t, *len))
+                        .map(|bytes| bytes.iter().map(|byte| format!("{:02x}", byte)).collect()),
+                };
+
+                (item.to_string(), value)
+            })
+            .collect();
+
+        let frame = TraceFrame { pc, instr_count, hit, values };
+        match &self.output {
+            LogOutput::Stdout => println!("{}", frame),
+            LogOutput::File { file, .. } => {
+                let _ = writeln!(file.lock().unwrap(), "{}", frame);
+            }
+            LogOutput::Trace => STATE.push_trace_frame(frame),
+        }
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[instr {}] {:#x} hit {}:", self.instr_count, self.pc, self.hit)?;
+        for (item, value) in &self.values {
+            write!(f, " {}={}", item, value.as_deref().unwrap_or("??"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogOutput::Stdout => f.write_str("stdout"),
+            LogOutput::File { path, .. } => f.write_str(path),
+            LogOutput::Trace => f.write_str("trace frames"),
+        }
+    }
+}
+
+/// Comparisons which must all hold for a conditional breakpoint to stop
+pub(crate) struct Condition(pub Vec<Comparison>);
+
+pub(crate) struct Comparison {
+    pub lhs: Operand,
+    pub op: CmpOp,
+    pub rhs: Operand,
+}
+
+pub(crate) enum Operand {
+    Number(u64),
+    Register(Reg),
+    /// A pointer-sized value read from the address the inner operand evaluates to
+    Deref(Box<Operand>),
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Condition {
+    pub(crate) fn eval(&self, cpu: &mut CPUState) -> bool {
+        self.0.iter().all(|comparison| comparison.eval(cpu))
+    }
+}
+
+impl Comparison {
+    fn eval(&self, cpu: &mut CPUState) -> bool {
+        // An unreadable operand can't satisfy anything
+        let (lhs, rhs) = match (self.lhs.eval(cpu), self.rhs.eval(cpu)) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            _ => return false,
+        };
+
+        match self.op {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl Operand {
+    pub(crate) fn eval(&self, cpu: &mut CPUState) -> Option<u64> {
+        match self {
+            Operand::Number(num) => Some(*num),
+            Operand::Register(reg) => Some(regs::get_reg(cpu, *reg) as u64),
+            Operand::Deref(addr) => {
+                let addr = addr.eval(cpu)?;
+                memory_map::read_ptr(cpu, addr as target_ptr_t).map(|value| value as u64)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, comparison) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, " && ")?;
+            }
+            write!(f, "{} {} {}", comparison.lhs, comparison.op, comparison.rhs)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for LogItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogItem::Value(operand) => write!(f, "{}", operand),
+            LogItem::Bytes(addr, len) => write!(f, "*{}:{}", addr, len),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Number(num) => write!(f, "{:#x}", num),
+            Operand::Register(reg) => write!(f, "{}", reg),
+            Operand::Deref(addr) => write!(f, "*{}", addr),
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        })
+    }
+}
@@ -0,0 +1,52 @@
+//! Reading the arguments of a process from its initial stack, since OSI doesn't
+//! expose them
+
+use panda::prelude::*;
+use panda::regs::{self, Reg};
+
+use crate::memory_map;
+
+#[cfg(feature = "x86_64")]
+const STACK_POINTER: Reg = Reg::RSP;
+#[cfg(feature = "i386")]
+const STACK_POINTER: Reg = Reg::ESP;
+#[cfg(any(feature = "arm", feature = "mips", feature = "mipsel"))]
+const STACK_POINTER: Reg = Reg::SP;
+#[cfg(feature = "ppc")]
+const STACK_POINTER: Reg = Reg::R1;
+
+/// Limits on what's read, in case the stack isn't what it's expected to be
+const MAX_ARGS: target_ptr_t = 256;
+const MAX_ARG_LEN: usize = 4096;
+
+/// Read `argv` of a process stopped on its first user mode instruction, where the
+/// stack holds `argc` followed by the `argv` pointers
+pub(crate) fn read_from_stack(cpu: &mut CPUState) -> Option<Vec<String>> {
+    let sp = regs::get_reg(cpu, STACK_POINTER) as target_ptr_t;
+    let ptr_size = std::mem::size_of::<target_ptr_t>() as target_ptr_t;
+
+    let argc = memory_map::read_ptr(cpu, sp)?;
+    if argc > MAX_ARGS {
+        return None;
+    }
+
+    (1..=argc)
+        .map(|i| {
+            let arg = memory_map::read_ptr(cpu, sp + i * ptr_size)?;
+            read_string(cpu, arg)
+        })
+        .collect()
+}
+
+fn read_string(cpu: &mut CPUState, addr: target_ptr_t) -> Option<String> {
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_ARG_LEN {
+        let byte = cpu.try_mem_read(addr + bytes.len() as target_ptr_t, 1)?[0];
+        if byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+    }
+
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
@@ -0,0 +1,15 @@
+use panda::prelude::*;
+use panda::plugins::osi::OSI;
+
+/// Describe a context switch using the names OSI has for the processes on either side
+pub(crate) fn describe(cpu: &mut CPUState, old_asid: target_ulong, new_asid: target_ulong) -> String {
+    let procs = OSI.get_processes(cpu);
+    let name = |asid: target_ulong| {
+        procs.iter()
+            .find(|proc| proc.asid as target_ulong == asid)
+            .map(|proc| format!("{} (PID {}, ASID {:#x})", proc.get_name(), proc.pid, asid))
+            .unwrap_or_else(|| format!("ASID {:#x}", asid))
+    };
+
+    format!("Context switch: {} -> {}", name(old_asid), name(new_asid))
+}
@@ -0,0 +1,135 @@
+//! Guest CPU exceptions that can be caught, and the signals they're reported to GDB as
+
+use crate::target_state::BreakStatus;
+
+/// GDB signal numbers
+const SIGILL: u8 = 4;
+pub(crate) const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGBUS: u8 = 10;
+const SIGSEGV: u8 = 11;
+
+/// (QEMU exception index, short name, signal reported to GDB)
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+pub(crate) const EXCEPTIONS: &[(i32, &str, u8)] = &[
+    (0, "de", SIGFPE),
+    (1, "db", SIGTRAP),
+    (3, "bp", SIGTRAP),
+    (4, "of", SIGSEGV),
+    (5, "br", SIGSEGV),
+    (6, "ud", SIGILL),
+    (7, "nm", SIGSEGV),
+    (10, "ts", SIGSEGV),
+    (11, "np", SIGBUS),
+    (12, "ss", SIGBUS),
+    (13, "gp", SIGSEGV),
+    (14, "pf", SIGSEGV),
+    (16, "mf", SIGFPE),
+    (17, "ac", SIGBUS),
+    (19, "xm", SIGFPE),
+    (0x80, "int80", SIGTRAP),
+];
+
+#[cfg(feature = "arm")]
+pub(crate) const EXCEPTIONS: &[(i32, &str, u8)] = &[
+    (1, "udef", SIGILL),
+    (3, "prefetch_abort", SIGSEGV),
+    (2, "swi", SIGTRAP),
+    (4, "data_abort", SIGSEGV),
+    (5, "irq", SIGTRAP),
+    (6, "fiq", SIGTRAP),
+    (7, "bkpt", SIGTRAP),
+];
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const EXCEPTIONS: &[(i32, &str, u8)] = &[
+    (8, "int", SIGTRAP),
+    (12, "adel", SIGBUS),
+    (13, "ades", SIGBUS),
+    (15, "ibe", SIGBUS),
+    (17, "syscall", SIGTRAP),
+    (18, "break", SIGTRAP),
+    (20, "ri", SIGILL),
+    (21, "overflow", SIGFPE),
+    (22, "trap", SIGTRAP),
+    (23, "fpe", SIGFPE),
+    (26, "tlbl", SIGSEGV),
+    (27, "tlbs", SIGSEGV),
+    (28, "dbe", SIGBUS),
+];
+
+#[cfg(feature = "ppc")]
+pub(crate) const EXCEPTIONS: &[(i32, &str, u8)] = &[
+    (2, "dsi", SIGSEGV),
+    (3, "isi", SIGSEGV),
+    (4, "external", SIGTRAP),
+    (5, "align", SIGBUS),
+    (6, "program", SIGILL),
+    (8, "syscall", SIGTRAP),
+    (10, "decr", SIGTRAP),
+];
+
+/// Exceptions which enter the kernel for a system call
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+const SYSCALLS: &[i32] = &[0x80];
+#[cfg(feature = "arm")]
+const SYSCALLS: &[i32] = &[2];
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+const SYSCALLS: &[i32] = &[17];
+#[cfg(feature = "ppc")]
+const SYSCALLS: &[i32] = &[8];
+
+/// Whether an exception index is an interrupt rather than a fault or a trap
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+fn is_interrupt(index: i32) -> bool {
+    // Vectors below 32 are reserved for CPU exceptions
+    index >= 32
+}
+
+#[cfg(feature = "arm")]
+fn is_interrupt(index: i32) -> bool {
+    index == 5 || index == 6
+}
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+fn is_interrupt(index: i32) -> bool {
+    index == 8
+}
+
+#[cfg(feature = "ppc")]
+fn is_interrupt(index: i32) -> bool {
+    index == 4 || index == 10
+}
+
+/// Why the guest stopped when a caught exception is raised
+pub(crate) fn break_status(index: i32) -> BreakStatus {
+    if SYSCALLS.contains(&index) {
+        BreakStatus::Syscall(index)
+    } else if is_interrupt(index) {
+        BreakStatus::Interrupt(index)
+    } else {
+        BreakStatus::Exception(index)
+    }
+}
+
+/// Look up an exception index by its short name
+pub(crate) fn by_name(name: &str) -> Option<i32> {
+    EXCEPTIONS.iter()
+        .find(|&&(_, exception_name, _)| exception_name == name)
+        .map(|&(index, _, _)| index)
+}
+
+pub(crate) fn name(index: i32) -> Option<&'static str> {
+    EXCEPTIONS.iter()
+        .find(|&&(exception_index, _, _)| exception_index == index)
+        .map(|&(_, name, _)| name)
+}
+
+/// Signal to report to GDB for an exception. Anything not known to be a fault
+/// (such as software interrupts) is reported as a trap.
+pub(crate) fn signal(index: i32) -> u8 {
+    EXCEPTIONS.iter()
+        .find(|&&(exception_index, _, _)| exception_index == index)
+        .map(|&(_, _, signal)| signal)
+        .unwrap_or(SIGTRAP)
+}
@@ -0,0 +1,39 @@
+//! Paths of a process's open file descriptors, through `osi_linux`
+
+use panda::prelude::*;
+use panda::plugins::osi::OsiProc;
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+
+panda::plugin_import!{
+    static OSI_LINUX: OsiLinux = extern "osi_linux" {
+        fn osi_linux_fd_to_filename(cpu: *mut CPUState, p: *mut OsiProc, fd: c_int) -> *mut c_char;
+    };
+}
+
+extern "C" {
+    fn g_free(mem: *mut c_void);
+}
+
+/// Path a file descriptor of a process refers to, if it is open
+pub(crate) fn path(cpu: &mut CPUState, proc: &mut OsiProc, fd: c_int) -> Option<String> {
+    let path = OSI_LINUX.osi_linux_fd_to_filename(cpu, proc, fd);
+    if path.is_null() {
+        return None;
+    }
+
+    // SAFETY: non-null results are strings allocated by osi_linux for us to free
+    let file = unsafe {
+        let file = CStr::from_ptr(path).to_string_lossy().into_owned();
+        g_free(path as *mut c_void);
+        file
+    };
+
+    Some(file)
+}
+
+/// Whether a path is a terminal, such as the guest's serial console or a pty
+pub(crate) fn is_terminal(path: &str) -> bool {
+    path.starts_with("/dev/tty") || path.starts_with("/dev/pts/") || path == "/dev/console"
+}
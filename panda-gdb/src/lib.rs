@@ -1,19 +1,36 @@
 use panda::prelude::*;
+use panda::plugins::osi::OSI;
 use gdbstub::GdbStub;
 
-use std::os::raw::{c_char, c_int};
+use std::os::raw::{c_char, c_int, c_void};
 use std::ffi::CStr;
+use std::net::TcpStream;
 
 mod panda_target;
 use panda_target::PandaTarget;
 
+mod backend;
+
 mod target_state;
 use target_state::{BreakStatus, STATE};
 
 mod connection;
 mod memory_map;
+mod paging;
+mod attach;
+mod exceptions;
+mod context_switch;
+mod process_exit;
+mod process_output;
+mod file_descriptors;
+mod command_line;
+mod breakpoints;
+mod branch_history;
 mod monitor_commands;
 
+#[cfg(all(test, feature = "x86_64"))]
+mod tests;
+
 mod args;
 use args::Args;
 
@@ -25,19 +42,24 @@ lazy_static::lazy_static!{
 fn init(_: &mut PluginHandle) -> bool {
     lazy_static::initialize(&ARGS);
     lazy_static::initialize(&STATE);
-    if ARGS.on_entry {
+    // Kernel mode already stops at the first instruction, so it doesn't wait for a
+    // process to enter user mode as well
+    if ARGS.on_entry && !ARGS.kernel {
         STATE.set_exit_kernel();
     }
 
-    if ARGS.on_start {
+    // There's no single process to return to when debugging the kernel
+    if ARGS.kernel {
+        STATE.set_user_only_stepping(false);
+    }
+
+    // Kernel mode debugs the whole system, so stop before the first instruction
+    if ARGS.on_start || ARGS.kernel {
         let connection = connection::wait_for_gdb();
         STATE.start_single_stepping();
 
         // Debugger runs in a seperate thread
-        std::thread::spawn(||{
-            let mut debugger = GdbStub::new(connection);
-            debugger.run(&mut PandaTarget)
-        });    
+        start_debugger(connection);
     }
 
     true
@@ -45,7 +67,7 @@ fn init(_: &mut PluginHandle) -> bool {
 
 #[panda::pre_shutdown]
 fn on_shutdown() {
-    STATE.brk.signal(BreakStatus::Exit);
+    exit(BreakStatus::Exit(0));
 }
 
 //#[panda::on_process_end]
@@ -87,35 +109,175 @@ fn every_instruction(cpu: &mut CPUState, pc: target_ptr_t) {
         // Once we exit the kernel start single stepping and stop watching for kernel to exit
         STATE.unset_exit_kernel();
         STATE.start_single_stepping();
+        let pid = OSI.get_current_process(cpu).pid as _;
+        STATE.set_pid(pid);
+        branch_history::start(panda::current_asid(cpu));
+        if let Some(args) = command_line::read_from_stack(cpu) {
+            STATE.set_command_line(pid, args);
+        }
 
         memory_map::print(cpu);
 
         let connection = connection::wait_for_gdb();
         // Debugger runs in a seperate thread
-        std::thread::spawn(||{
-            let mut debugger = GdbStub::new(connection);
-            debugger.run(&mut PandaTarget)
-        });
+        start_debugger(connection);
     }
 
-    // Break if single stepping or if we hit a breakpoint
-    if STATE.single_stepping() || STATE.breakpoints_contain(pc) {
-        // Mark single step as completed
+    // Stop in the process being attached to once it's scheduled
+    if attach::reached_attach_target(cpu) {
+        STATE.clear_attach_target();
+        STATE.start_single_stepping();
+    }
+
+    // Report the exit before the process is gone, and show what the process writes
+    if STATE.is_pid_set() && process_exit::is_syscall(cpu, pc) {
+        if let Some(status) = process_exit::exit_group_status(cpu) {
+            exit(status);
+            return
+        }
+
+        process_output::forward_write(cpu);
+    }
+
+    // Break if we hit a breakpoint or are single stepping. Breakpoints are checked
+    // first so a step landing on one reports the breakpoint, and so log points keep
+    // counting while stepping.
+    if STATE.hit_breakpoint(cpu, pc) {
+        stop(cpu, pc, BreakStatus::Breakpoint(pc));
+    } else if STATE.step_entered_kernel(cpu) {
+        // Stop instrumenting new blocks until the step is back in user mode, which
+        // before_block watches for
         STATE.stop_single_stepping();
-        // Pass the CPU to the debugging thread
-        STATE.set_cpu(cpu);
-        STATE.set_pc(pc);
-        // Signal the process has breaked
-        STATE.brk.signal(BreakStatus::Break);
-        // Wait for the signal to begin running again
-        STATE.cont.wait_for();
-        // Revoke the CPU from the debugging thread
-        STATE.unset_cpu();
+    } else if STATE.single_stepping_on(cpu, pc) {
+        stop(cpu, pc, BreakStatus::StepDone);
+    }
+}
+
+// Checked per block rather than per instruction, so the kernel runs at full speed
+#[panda::before_block_exec]
+fn before_block(cpu: &mut CPUState, tb: &mut TranslationBlock) {
+    if STATE.finished_kernel(cpu) {
+        stop(cpu, tb.pc as _, BreakStatus::KernelReturn);
+    } else if STATE.step_returned_from_kernel(cpu) {
+        let pc = tb.pc as target_ptr_t;
+        if STATE.in_step_range(pc) {
+            // Still stepping through the range, so check every instruction again
+            STATE.start_single_stepping();
+            unsafe {
+                panda::sys::panda_do_flush_tb();
+            }
+        } else {
+            stop(cpu, pc, BreakStatus::StepDone);
+        }
+    }
+}
+
+#[panda::after_block_exec]
+fn after_block(cpu: &mut CPUState, tb: &mut TranslationBlock, exit_code: u8) {
+    // Blocks cut short, by an interrupt for example, run again from the start later
+    if u32::from(exit_code) <= panda::sys::TB_EXIT_IDX1 {
+        branch_history::record(cpu, tb);
+    }
+}
+
+#[panda::virt_mem_after_read]
+fn on_read(cpu: &mut CPUState, pc: target_ptr_t, addr: target_ptr_t, size: usize, _buf: *mut c_void) {
+    if let Some((kind, watched)) = STATE.hit_watchpoint(addr, size, false) {
+        stop(cpu, pc, BreakStatus::Watchpoint(kind, watched));
+    }
+}
+
+#[panda::virt_mem_after_write]
+fn on_write(cpu: &mut CPUState, pc: target_ptr_t, addr: target_ptr_t, size: usize, _buf: *mut c_void) {
+    if let Some((kind, watched)) = STATE.hit_watchpoint(addr, size, true) {
+        stop(cpu, pc, BreakStatus::Watchpoint(kind, watched));
     }
 }
 
+#[panda::before_handle_exception]
+fn on_exception(cpu: &mut CPUState, exception_index: i32) -> i32 {
+    if STATE.catches_exception(exception_index, panda::current_asid(cpu)) {
+        // Faults are raised with the CPU state restored to the faulting instruction
+        let pc = panda::current_pc(cpu);
+        stop(cpu, pc, exceptions::break_status(exception_index));
+    }
+
+    exception_index
+}
+
+#[panda::asid_changed]
+fn on_asid_change(cpu: &mut CPUState, old_asid: target_ulong, new_asid: target_ulong) -> bool {
+    if let Some(asid) = STATE.switch_catch_asid() {
+        if old_asid != new_asid && (old_asid == asid || new_asid == asid) {
+            let description = context_switch::describe(cpu, old_asid, new_asid);
+            println!("{}", description);
+            STATE.set_last_switch(description);
+
+            let pc = panda::current_pc(cpu);
+            stop(cpu, pc, BreakStatus::ContextSwitch);
+        }
+    }
+
+    // Never prevent the ASID change
+    false
+}
+
+/// Hand the CPU to the debugger thread and block until GDB resumes the guest
+fn stop(cpu: &mut CPUState, pc: target_ptr_t, status: BreakStatus) {
+    // Nobody is left to resume the guest once GDB has been told the process exited
+    if STATE.finished_debugging() {
+        return
+    }
+
+    // Mark single step as completed
+    STATE.stop_single_stepping();
+    STATE.clear_step_return_asid();
+    STATE.clear_step_range();
+    STATE.clear_finish_kernel();
+    STATE.set_stopped(cpu, pc);
+    // Signal the process has breaked
+    STATE.brk.signal(status);
+    // Run what the debugger asks of the CPU until it resumes the guest
+    STATE.serve_requests(cpu);
+}
+
+/// Serve GDB from a separate thread
+fn start_debugger(connection: TcpStream) {
+    // Output from the debugged process is sent to GDB from the PANDA thread
+    if let Ok(console) = connection.try_clone() {
+        STATE.set_console(console);
+    }
+
+    std::thread::spawn(|| {
+        // Wait for the guest to stop on its own before GDB first resumes it, so that
+        // stop isn't taken as the result of the first resume
+        STATE.brk.wait_for();
+
+        let mut debugger = GdbStub::new(connection);
+        debugger.run(&mut PandaTarget::new(backend::Panda))
+    });
+}
+
+/// Tell GDB the debugged process is gone. gdbstub disconnects after reporting an
+/// exit, so the guest keeps running rather than waiting to be resumed.
+fn exit(status: BreakStatus) {
+    if STATE.finished_debugging() {
+        return
+    }
+
+    STATE.finish_debugging();
+    STATE.stop_single_stepping();
+    STATE.unset_pid();
+    branch_history::stop();
+    STATE.brk.signal(status);
+}
+
 #[panda::insn_translate]
-fn translate_instr(_: &mut CPUState, pc: target_ptr_t) -> bool {
+fn translate_instr(cpu: &mut CPUState, pc: target_ptr_t) -> bool {
     // Only instrument the instruction if we might break on it
-    STATE.single_stepping() || STATE.breakpoints_contain(pc) || STATE.exited_kernel(pc)
+    STATE.single_stepping()
+        || STATE.breakpoints_contain(pc)
+        || STATE.exited_kernel(pc)
+        || STATE.attach_target().is_some()
+        || (STATE.is_pid_set() && process_exit::is_syscall(cpu, pc))
 }
@@ -1,62 +1,201 @@
 use panda::prelude::*;
-use panda::plugins::osi::OSI;
+use panda::plugins::osi::{OSI, OsiModule, OsiProc};
 
 use std::ffi::CStr;
 use gdbstub::outputln;
+use serde::Serialize;
 
-pub(crate) fn print(cpu: &mut CPUState) {
-    let mut proc = OSI.get_current_process(cpu);
-    let mappings = OSI.get_mappings(cpu, &mut *proc);
-
-    println!("Memory map:");
-    for mapping in mappings.iter() {
-        let start = mapping.base;
-        let end = mapping.base + mapping.size;
+/// An owned copy of an OSI mapping, so it can outlive the OSI-allocated list
+pub(crate) struct Mapping {
+    pub base: target_ptr_t,
+    pub size: target_ptr_t,
+    pub modd: target_ptr_t,
+    pub name: Option<String>,
+    pub file: Option<String>,
+}
 
-        let name = if !mapping.file.is_null() {
-            // SAFETY: ptr must be non-null (checked) and must be valid (can assume so due to OSI)
-            let file = unsafe { CStr::from_ptr(mapping.file) };
-            let file = file.to_string_lossy();
+/// A mapping as printed by `meminfo --json`, with addresses in hex like GDB shows them
+#[derive(Serialize)]
+pub(crate) struct MappingJson<'a> {
+    base: String,
+    end: String,
+    modd: String,
+    name: Option<&'a str>,
+    file: Option<&'a str>,
+}
 
-            file.to_string()
-        } else if !mapping.name.is_null() {
-            let name = unsafe { CStr::from_ptr(mapping.name) };
-            let name = name.to_string_lossy();
+impl Mapping {
+    fn from_osi(mapping: &OsiModule) -> Self {
+        // SAFETY: ptrs must be non-null (checked) and must be valid (can assume so due to OSI)
+        let file = if !mapping.file.is_null() {
+            Some(unsafe { CStr::from_ptr(mapping.file) }.to_string_lossy().into_owned())
+        } else {
+            None
+        };
 
-            name.to_string()
+        let name = if !mapping.name.is_null() {
+            Some(unsafe { CStr::from_ptr(mapping.name) }.to_string_lossy().into_owned())
         } else {
-            "[unnamed]".to_owned()
+            None
         };
 
-        println!("{:x}-{:x} {:x}    {}", start, end, mapping.modd, name);
+        Mapping {
+            base: mapping.base as _,
+            size: mapping.size as _,
+            modd: mapping.modd as _,
+            name,
+            file,
+        }
+    }
+
+    pub(crate) fn end(&self) -> target_ptr_t {
+        self.base + self.size
+    }
+
+    pub(crate) fn contains(&self, addr: target_ptr_t) -> bool {
+        (self.base..self.end()).contains(&addr)
+    }
+
+    /// Whether the mapping is backed by a file, which is the closest thing OSI gives
+    /// us to knowing a mapping holds code
+    pub(crate) fn is_file_backed(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub(crate) fn to_json(&self) -> MappingJson<'_> {
+        MappingJson {
+            base: format!("{:#x}", self.base),
+            end: format!("{:#x}", self.end()),
+            modd: format!("{:#x}", self.modd),
+            name: self.name.as_deref(),
+            file: self.file.as_deref(),
+        }
+    }
+
+    /// Full name of the mapping, as shown by `meminfo`
+    pub(crate) fn display_name(&self) -> &str {
+        self.file
+            .as_deref()
+            .or_else(|| self.name.as_deref())
+            .unwrap_or("[unnamed]")
+    }
+
+    /// Short name of the mapping, used when printing `module+offset`
+    pub(crate) fn short_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or_else(|| self.file.as_deref())
+            .unwrap_or("[unnamed]")
     }
 }
 
-pub(crate) fn print_to_gdb(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
+/// Get the memory mappings of the current process
+pub(crate) fn get(cpu: &mut CPUState) -> Vec<Mapping> {
     let mut proc = OSI.get_current_process(cpu);
-    let mappings = OSI.get_mappings(cpu, &mut *proc);
 
-    outputln!(out);
-    outputln!(out, "Memory map:");
-    for mapping in mappings.iter() {
-        let start = mapping.base;
-        let end = mapping.base + mapping.size;
+    get_for(cpu, &mut *proc)
+}
 
-        let name = if !mapping.file.is_null() {
-            // SAFETY: ptr must be non-null (checked) and must be valid (can assume so due to OSI)
-            let file = unsafe { CStr::from_ptr(mapping.file) };
-            let file = file.to_string_lossy();
+/// Get the memory mappings of any process
+pub(crate) fn get_for(cpu: &mut CPUState, proc: &mut OsiProc) -> Vec<Mapping> {
+    OSI.get_mappings(cpu, proc).iter().map(Mapping::from_osi).collect()
+}
 
-            file.to_string()
-        } else if !mapping.name.is_null() {
-            let name = unsafe { CStr::from_ptr(mapping.name) };
-            let name = name.to_string_lossy();
+/// Get the kernel modules currently loaded in the guest
+pub(crate) fn kernel_modules(cpu: &mut CPUState) -> Vec<Mapping> {
+    OSI.get_modules(cpu).iter().map(Mapping::from_osi).collect()
+}
 
-            name.to_string()
-        } else {
-            "[unnamed]".to_owned()
-        };
+/// Group the file-backed mappings of a process by file, giving one mapping per loaded
+/// module which spans from its lowest to its highest mapping, in the order they're mapped
+pub(crate) fn modules(mappings: &[Mapping]) -> Vec<Mapping> {
+    let mut modules: Vec<Mapping> = Vec::new();
+
+    for mapping in mappings.iter().filter(|mapping| mapping.is_file_backed()) {
+        match modules.iter_mut().find(|module| module.file == mapping.file) {
+            Some(module) => {
+                let end = module.end().max(mapping.end());
+                module.base = module.base.min(mapping.base);
+                module.size = end - module.base;
+            }
+            None => modules.push(Mapping {
+                base: mapping.base,
+                size: mapping.size,
+                modd: mapping.modd,
+                name: mapping.name.clone(),
+                file: mapping.file.clone(),
+            }),
+        }
+    }
 
-        outputln!(out, "{:x}-{:x} {:x}    {}", start, end, mapping.modd, name);
+    modules
+}
+
+/// Find the mapping containing a given address
+pub(crate) fn find(mappings: &[Mapping], addr: target_ptr_t) -> Option<&Mapping> {
+    mappings.iter().find(|mapping| mapping.contains(addr))
+}
+
+/// Format an address as `module+offset`, where the offset is relative to the lowest
+/// mapping of the same file so that it matches offsets within the module on disk.
+pub(crate) fn symbolize(mappings: &[Mapping], addr: target_ptr_t) -> Option<String> {
+    let mapping = find(mappings, addr)?;
+    let module_base = match &mapping.file {
+        Some(file) => mappings.iter()
+            .filter(|other| other.file.as_ref() == Some(file))
+            .map(|other| other.base)
+            .min()
+            .unwrap_or(mapping.base),
+        None => mapping.base,
+    };
+
+    Some(format!("{}+{:#x}", mapping.short_name(), addr - module_base))
+}
+
+/// Read a pointer-sized value from guest memory in guest byte order
+pub(crate) fn read_ptr(cpu: &mut CPUState, addr: target_ptr_t) -> Option<target_ptr_t> {
+    let bytes = cpu.try_mem_read(addr, std::mem::size_of::<target_ptr_t>())?;
+
+    ptr_from_bytes(&bytes)
+}
+
+/// Decode a pointer-sized value in guest byte order
+pub(crate) fn ptr_from_bytes(bytes: &[u8]) -> Option<target_ptr_t> {
+    use std::convert::TryInto;
+
+    let bytes = bytes.try_into().ok()?;
+
+    #[cfg(any(feature = "mips", feature = "ppc"))] {
+        Some(target_ptr_t::from_be_bytes(bytes))
+    }
+
+    #[cfg(not(any(feature = "mips", feature = "ppc")))] {
+        Some(target_ptr_t::from_le_bytes(bytes))
+    }
+}
+
+/// Encode a pointer-sized value in guest byte order
+pub(crate) fn ptr_to_bytes(ptr: target_ptr_t) -> Vec<u8> {
+    #[cfg(any(feature = "mips", feature = "ppc"))] {
+        ptr.to_be_bytes().to_vec()
+    }
+
+    #[cfg(not(any(feature = "mips", feature = "ppc")))] {
+        ptr.to_le_bytes().to_vec()
+    }
+}
+
+pub(crate) fn print(cpu: &mut CPUState) {
+    println!("Memory map:");
+    for mapping in get(cpu) {
+        println!("{:x}-{:x} {:x}    {}", mapping.base, mapping.end(), mapping.modd, mapping.display_name());
+    }
+}
+
+pub(crate) fn print_to_gdb(mappings: &[Mapping], mut out: impl std::fmt::Write) {
+    outputln!(out);
+    outputln!(out, "Memory map:");
+    for mapping in mappings {
+        outputln!(out, "{:x}-{:x} {:x}    {}", mapping.base, mapping.end(), mapping.modd, mapping.display_name());
     }
 }
@@ -0,0 +1,163 @@
+use panda::prelude::*;
+use panda::regs::Reg;
+
+use gdbstub::outputln;
+
+use crate::backend::Guest;
+use crate::memory_map::{self, Mapping};
+
+const PTR_SIZE: target_ptr_t = std::mem::size_of::<target_ptr_t>() as target_ptr_t;
+
+/// The stack pointer, frame pointer and (if the architecture has one) link register
+#[cfg(feature = "x86_64")]
+const FRAME_REGS: (Reg, Reg, Option<Reg>) = (Reg::RSP, Reg::RBP, None);
+#[cfg(feature = "i386")]
+const FRAME_REGS: (Reg, Reg, Option<Reg>) = (Reg::ESP, Reg::EBP, None);
+#[cfg(feature = "arm")]
+const FRAME_REGS: (Reg, Reg, Option<Reg>) = (Reg::SP, Reg::R11, Some(Reg::LR));
+#[cfg(feature = "ppc")]
+const FRAME_REGS: (Reg, Reg, Option<Reg>) = (Reg::R1, Reg::R1, Some(Reg::LR));
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+const FRAME_REGS: (Reg, Reg, Option<Reg>) = (Reg::SP, Reg::FP, Some(Reg::RA));
+
+/// How far up the stack to scan for return addresses once frame pointers run out
+const MAX_SCAN_BYTES: target_ptr_t = 0x4000;
+
+pub(crate) const DEFAULT_MAX_FRAMES: usize = 32;
+
+#[derive(Clone, Copy)]
+enum FrameSource {
+    Pc,
+    FramePointer,
+    LinkRegister,
+    Scan,
+}
+
+struct Frame {
+    addr: target_ptr_t,
+    source: FrameSource,
+}
+
+pub(crate) fn print(guest: &mut dyn Guest, max_frames: usize, mut out: impl std::fmt::Write) {
+    let mappings = guest.mappings();
+    let frames = unwind(guest, &mappings, max_frames);
+
+    outputln!(out);
+    for (i, frame) in frames.iter().enumerate() {
+        let location = memory_map::symbolize(&mappings, frame.addr)
+            .unwrap_or_else(|| "??".to_owned());
+        let note = match frame.source {
+            FrameSource::Pc | FrameSource::FramePointer => "",
+            FrameSource::LinkRegister => " (link register)",
+            FrameSource::Scan => " (scanned)",
+        };
+
+        outputln!(out, "#{:<3} {:#x} in {}{}", i, frame.addr, location, note);
+    }
+    outputln!(out);
+}
+
+fn unwind(guest: &mut dyn Guest, mappings: &[Mapping], max_frames: usize) -> Vec<Frame> {
+    let (sp_reg, fp_reg, link_reg) = FRAME_REGS;
+    let sp = guest.register(sp_reg).unwrap_or(0) as target_ptr_t;
+    let fp = guest.register(fp_reg).unwrap_or(0) as target_ptr_t;
+    let mut frames = vec![Frame { addr: guest.pc(), source: FrameSource::Pc }];
+
+    if let Some(ret) = link_reg.and_then(|reg| guest.register(reg)) {
+        let ret = ret as target_ptr_t;
+        if is_return_addr(guest, mappings, ret) {
+            frames.push(Frame { addr: ret, source: FrameSource::LinkRegister });
+        }
+    }
+
+    // Follow the saved frame pointer chain for as long as it looks sane
+    let mut scan_start = sp;
+    let mut fp = fp;
+    while cfg!(any(feature = "x86_64", feature = "i386")) && frames.len() < max_frames {
+        if fp < sp || fp % PTR_SIZE != 0 {
+            break
+        }
+
+        let (next_fp, ret) = match (
+            read_ptr(guest, fp),
+            read_ptr(guest, fp + PTR_SIZE)
+        ) {
+            (Some(next_fp), Some(ret)) => (next_fp, ret),
+            _ => break,
+        };
+
+        if !is_return_addr(guest, mappings, ret) {
+            break
+        }
+
+        frames.push(Frame { addr: ret, source: FrameSource::FramePointer });
+        scan_start = fp + (2 * PTR_SIZE);
+
+        // The stack grows down, so each caller's frame must be above the callee's
+        if next_fp <= fp {
+            break
+        }
+        fp = next_fp;
+    }
+
+    // Without (more) frame pointers, fall back to scanning the stack for anything
+    // that looks like a return address
+    let mut addr = scan_start;
+    let scan_end = scan_start.saturating_add(MAX_SCAN_BYTES);
+    while addr < scan_end && frames.len() < max_frames {
+        let value = match read_ptr(guest, addr) {
+            Some(value) => value,
+            None => break,
+        };
+
+        let is_new = frames.last().map(|frame| frame.addr != value).unwrap_or(true);
+        if is_new && is_return_addr(guest, mappings, value) {
+            frames.push(Frame { addr: value, source: FrameSource::Scan });
+        }
+
+        addr += PTR_SIZE;
+    }
+
+    frames
+}
+
+/// Whether a value points into a file-backed mapping, right after something that
+/// could have been a call instruction
+fn is_return_addr(guest: &mut dyn Guest, mappings: &[Mapping], addr: target_ptr_t) -> bool {
+    match memory_map::find(mappings, addr) {
+        Some(mapping) if mapping.is_file_backed() => follows_call(guest, addr),
+        _ => false,
+    }
+}
+
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+fn follows_call(guest: &mut dyn Guest, addr: target_ptr_t) -> bool {
+    const MAX_CALL_LEN: usize = 7;
+
+    let before = match addr.checked_sub(MAX_CALL_LEN as target_ptr_t)
+        .and_then(|start| guest.read_memory(start, MAX_CALL_LEN))
+    {
+        Some(before) => before,
+        None => return false,
+    };
+
+    // call rel32
+    if before[MAX_CALL_LEN - 5] == 0xe8 {
+        return true
+    }
+
+    // call r/m (ff /2), followed by up to 5 bytes of SIB and displacement
+    (2..=MAX_CALL_LEN).any(|len| {
+        let opcode = MAX_CALL_LEN - len;
+        before[opcode] == 0xff && (before[opcode + 1] >> 3) & 0b111 == 2
+    })
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "i386")))]
+fn follows_call(_guest: &mut dyn Guest, addr: target_ptr_t) -> bool {
+    addr % 4 == 0
+}
+
+fn read_ptr(guest: &mut dyn Guest, addr: target_ptr_t) -> Option<target_ptr_t> {
+    memory_map::ptr_from_bytes(&guest.read_memory(addr, PTR_SIZE as usize)?)
+}
@@ -0,0 +1,103 @@
+use panda::prelude::*;
+
+use gdbstub::outputln;
+use tabwriter::TabWriter;
+
+use std::io::Write;
+
+use crate::backend::Guest;
+use crate::breakpoints::{Breakpoint, Condition, LogItem, LogOutput, LogPoint};
+
+pub(crate) fn add(
+    guest: &mut dyn Guest,
+    addr: target_ptr_t,
+    condition: Option<Condition>,
+    mut out: impl std::fmt::Write,
+) {
+    match &condition {
+        Some(condition) => outputln!(out, "Breakpoint at {:#x} if {}", addr, condition),
+        None => outputln!(out, "Breakpoint at {:#x}", addr),
+    }
+
+    insert(guest, addr, Breakpoint::new(condition, None));
+}
+
+pub(crate) fn add_log(
+    guest: &mut dyn Guest,
+    addr: target_ptr_t,
+    items: Vec<LogItem>,
+    condition: Option<Condition>,
+    path: Option<String>,
+    mut out: impl std::fmt::Write,
+) {
+    let log = match LogPoint::new(items, path) {
+        Ok(log) => log,
+        Err(err) => {
+            outputln!(out, "Failed to open the log file: {}", err);
+            return
+        }
+    };
+
+    outputln!(out, "Log point at {:#x}, writing to {}", addr, log.output);
+    insert(guest, addr, Breakpoint::new(condition, Some(log)));
+}
+
+pub(crate) fn add_trace(
+    guest: &mut dyn Guest,
+    addr: target_ptr_t,
+    items: Vec<LogItem>,
+    condition: Option<Condition>,
+    mut out: impl std::fmt::Write,
+) {
+    outputln!(out, "Tracepoint at {:#x}, browse what it collects with tfind", addr);
+    insert(guest, addr, Breakpoint::new(condition, Some(LogPoint::trace(items))));
+}
+
+fn insert(guest: &mut dyn Guest, addr: target_ptr_t, breakpoint: Breakpoint) {
+    guest.add_monitor_breakpoint(addr, breakpoint);
+
+    // Blocks containing the address may already be translated without a check
+    guest.flush_translations();
+}
+
+pub(crate) fn remove(guest: &mut dyn Guest, addr: target_ptr_t, mut out: impl std::fmt::Write) {
+    if guest.remove_monitor_breakpoint(addr) {
+        outputln!(out, "Deleted breakpoint at {:#x}", addr);
+    } else {
+        outputln!(out, "No breakpoint at {:#x}, see bp_list", addr);
+    }
+}
+
+pub(crate) fn list(guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
+    outputln!(out);
+
+    let output = Vec::new();
+    let mut output = TabWriter::new(output).padding(2);
+
+    let _ = writeln!(output, "Address\tType\tHits\tCondition\tLogs");
+
+    #[allow(unused_must_use)]
+    guest.for_each_monitor_breakpoint(&mut |addr, breakpoint| {
+        let condition = breakpoint.condition.as_ref()
+            .map(|condition| condition.to_string())
+            .unwrap_or_else(|| "-".to_owned());
+
+        let (kind, logs) = match &breakpoint.log {
+            Some(log) => {
+                let items: Vec<String> = log.items.iter().map(|item| item.to_string()).collect();
+                let kind = match log.output {
+                    LogOutput::Trace => "trace",
+                    _ => "log",
+                };
+                (kind, format!("{} -> {}", items.join(" "), log.output))
+            }
+            None => ("break", "-".to_owned()),
+        };
+
+        writeln!(output, "{:#x}\t{}\t{}\t{}\t{}", addr, kind, breakpoint.hits(), condition, logs);
+    });
+
+    let _ = output.flush();
+    let output = String::from_utf8(output.into_inner().unwrap()).unwrap();
+    outputln!(out, "{}", output);
+}
@@ -0,0 +1,35 @@
+use gdbstub::outputln;
+
+use crate::backend::Guest;
+use crate::memory_map;
+
+pub(crate) const DEFAULT_COUNT: usize = 16;
+
+pub(crate) fn print(guest: &mut dyn Guest, count: usize, mut out: impl std::fmt::Write) {
+    let branches = guest.branches(count);
+
+    outputln!(out);
+    if branches.is_empty() {
+        outputln!(out, "No branches recorded, they're only recorded for a debugged process in user mode");
+        outputln!(out);
+        return
+    }
+
+    let mappings = guest.mappings();
+    let symbolize = |addr| memory_map::symbolize(&mappings, addr).unwrap_or_else(|| "??".to_owned());
+
+    outputln!(out, "Taken branches, most recent first, from the block that ended with each:");
+    for (i, branch) in branches.iter().enumerate() {
+        outputln!(
+            out,
+            "#{:<3} {:#x}..{:#x} in {} -> {:#x} in {}",
+            i,
+            branch.block_start,
+            branch.block_end,
+            symbolize(branch.block_start),
+            branch.target,
+            symbolize(branch.target)
+        );
+    }
+    outputln!(out);
+}
@@ -0,0 +1,83 @@
+use panda::prelude::*;
+
+use gdbstub::outputln;
+
+use crate::backend::Guest;
+use crate::exceptions;
+
+pub(crate) fn add(guest: &mut dyn Guest, exception: i32, current_process_only: bool, mut out: impl std::fmt::Write) {
+    let asid = if current_process_only {
+        Some(guest.asid())
+    } else {
+        None
+    };
+
+    guest.add_catchpoint(exception, asid);
+
+    match asid {
+        Some(asid) => outputln!(out, "Catching exception {} in ASID {:#x}", describe(exception), asid),
+        None => outputln!(out, "Catching exception {}", describe(exception)),
+    }
+}
+
+pub(crate) fn remove(guest: &mut dyn Guest, exception: i32, mut out: impl std::fmt::Write) {
+    if guest.remove_catchpoint(exception) {
+        outputln!(out, "No longer catching exception {}", describe(exception));
+    } else {
+        outputln!(out, "Exception {} wasn't being caught", describe(exception));
+    }
+}
+
+pub(crate) fn list(guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
+    let catchpoints = guest.catchpoints();
+
+    outputln!(out);
+    if catchpoints.is_empty() {
+        outputln!(out, "No exceptions are being caught");
+    }
+
+    for (exception, asid) in catchpoints {
+        match asid {
+            Some(asid) => outputln!(out, "  {} (ASID {:#x} only)", describe(exception), asid),
+            None => outputln!(out, "  {}", describe(exception)),
+        }
+    }
+
+    outputln!(out);
+    outputln!(out, "Known exceptions:");
+    for &(index, name, signal) in exceptions::EXCEPTIONS {
+        outputln!(out, "  {:>3} {:<16} reported as signal {}", index, name, signal);
+    }
+    outputln!(out);
+}
+
+fn describe(exception: i32) -> String {
+    match exceptions::name(exception) {
+        Some(name) => format!("{} ({})", exception, name),
+        None => exception.to_string(),
+    }
+}
+
+pub(crate) fn switches(guest: &mut dyn Guest, enabled: Option<bool>, mut out: impl std::fmt::Write) {
+    match enabled {
+        Some(true) => {
+            let asid = guest.asid();
+            guest.set_switch_catch_asid(Some(asid));
+            outputln!(out, "Stopping whenever ASID {:#x} is scheduled in or out", asid);
+        }
+        Some(false) => {
+            guest.set_switch_catch_asid(None);
+            outputln!(out, "No longer stopping on context switches");
+        }
+        None => {
+            match guest.switch_catch_asid() {
+                Some(asid) => outputln!(out, "Stopping whenever ASID {:#x} is scheduled in or out", asid),
+                None => outputln!(out, "Not stopping on context switches"),
+            }
+
+            if let Some(last_switch) = guest.last_switch() {
+                outputln!(out, "Last stop: {}", last_switch);
+            }
+        }
+    }
+}
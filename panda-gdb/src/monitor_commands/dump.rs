@@ -0,0 +1,156 @@
+use panda::prelude::*;
+
+use gdbstub::outputln;
+use serde::Serialize;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use super::parser::DumpTarget;
+use crate::backend::Guest;
+
+const PAGE_SIZE: target_ptr_t = 0x1000;
+
+/// Largest range dumped at once, since the guest is stopped until the dump is written
+const MAX_DUMP_SIZE: target_ptr_t = 0x4000_0000;
+
+/// Byte used in place of the contents of pages that couldn't be read
+const UNREADABLE_FILL: u8 = 0xcc;
+
+/// Sidecar written next to the dump, describing where the bytes came from
+#[derive(Serialize)]
+struct DumpInfo {
+    name: Option<String>,
+    base: String,
+    size: u64,
+    unreadable_fill: String,
+    regions: Vec<Region>,
+    unreadable_pages: Vec<String>,
+}
+
+/// A run of consecutive pages sharing the same permissions
+#[derive(Serialize)]
+struct Region {
+    base: String,
+    size: u64,
+    permissions: String,
+}
+
+pub(crate) fn dump(guest: &mut dyn Guest, target: DumpTarget, path: &str, mut out: impl std::fmt::Write) {
+    let (name, start, end) = match target {
+        DumpTarget::Range(start, end) => (None, start, end),
+        DumpTarget::Mapping(name) => {
+            let mappings = guest.mappings();
+            let mut matches = mappings.iter()
+                .filter(|mapping| {
+                    mapping.short_name() == name || mapping.display_name() == name
+                });
+
+            let mapping = match matches.next() {
+                Some(mapping) => mapping,
+                None => {
+                    outputln!(out, "No mapping named {:?}, see meminfo", name);
+                    return
+                }
+            };
+
+            for other in matches {
+                outputln!(
+                    out,
+                    "Note: {} is also mapped at {:#x}-{:#x}, dump it by range if needed",
+                    name, other.base, other.end()
+                );
+            }
+
+            (Some(mapping.display_name().to_owned()), mapping.base, mapping.end())
+        }
+    };
+
+    if end <= start {
+        outputln!(out, "End address must be after the start address");
+        return
+    }
+
+    if end - start > MAX_DUMP_SIZE {
+        outputln!(out, "Dumps are limited to {:#x} bytes, dump a smaller range", MAX_DUMP_SIZE);
+        return
+    }
+
+    // Pages are written as they're read, rather than collecting the whole range first
+    let mut file = match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            outputln!(out, "Failed to write {}: {}", path, err);
+            return
+        }
+    };
+
+    let mut size: u64 = 0;
+    let mut regions: Vec<Region> = Vec::new();
+    let mut unreadable_pages = Vec::new();
+
+    let mut page = start;
+    while page < end {
+        let chunk_end = ((page & !(PAGE_SIZE - 1)).saturating_add(PAGE_SIZE)).min(end);
+        let len = (chunk_end - page) as usize;
+
+        let written = match guest.read_memory(page, len) {
+            Some(bytes) => file.write_all(&bytes),
+            None => {
+                unreadable_pages.push(format!("{:#x}", page));
+                file.write_all(&vec![UNREADABLE_FILL; len])
+            }
+        };
+
+        if let Err(err) = written {
+            outputln!(out, "Failed to write {}: {}", path, err);
+            return
+        }
+        size += len as u64;
+
+        let permissions = match guest.page_walk(page) {
+            Some(walk) => walk.permissions().unwrap_or_else(|| "---".to_owned()),
+            None => "unknown".to_owned(),
+        };
+
+        match regions.last_mut() {
+            Some(region) if region.permissions == permissions => region.size += len as u64,
+            _ => regions.push(Region {
+                base: format!("{:#x}", page),
+                size: len as u64,
+                permissions,
+            }),
+        }
+
+        page = chunk_end;
+    }
+
+    if let Err(err) = file.flush() {
+        outputln!(out, "Failed to write {}: {}", path, err);
+        return
+    }
+
+    let info = DumpInfo {
+        name,
+        base: format!("{:#x}", start),
+        size,
+        unreadable_fill: format!("{:#x}", UNREADABLE_FILL),
+        regions,
+        unreadable_pages,
+    };
+
+    let info_path = format!("{}.json", path);
+    let written = serde_json::to_string_pretty(&info)
+        .map_err(|err| err.to_string())
+        .and_then(|info| std::fs::write(&info_path, info).map_err(|err| err.to_string()));
+
+    if let Err(err) = written {
+        outputln!(out, "Failed to write {}: {}", info_path, err);
+    }
+
+    outputln!(
+        out,
+        "Wrote {:#x} bytes from {:#x}-{:#x} to {} ({} unreadable pages)",
+        size, start, end, path, info.unreadable_pages.len()
+    );
+}
@@ -0,0 +1,312 @@
+use panda::regs::Reg;
+
+use gdbstub::outputln;
+
+/// Usage of a monitor command, for `help` and `help COMMAND`
+struct CommandHelp {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    examples: &'static [&'static str],
+}
+
+const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "meminfo",
+        usage: "meminfo",
+        description: "print out the current memory map",
+        examples: &["meminfo --json"],
+    },
+    CommandHelp {
+        name: "taint",
+        usage: "taint <*ADDR|REGISTER> LABEL",
+        description: "apply taint to a given register/memory location",
+        examples: &["taint *0x7fffffffe000 1", "taint rax 2", "taint *$rsp+8 3"],
+    },
+    CommandHelp {
+        name: "check_taint",
+        usage: "check_taint <*ADDR|REGISTER>",
+        description: "check if a given register/memory location is tainted",
+        examples: &["check_taint *0x7fffffffe000", "check_taint rax"],
+    },
+    CommandHelp {
+        name: "get_taint",
+        usage: "get_taint <*ADDR|REGISTER>",
+        description: "get the taint labels for a given register/memory location",
+        examples: &["get_taint *$rdi", "get_taint rax --json"],
+    },
+    CommandHelp {
+        name: "threadinfo",
+        usage: "threadinfo [PID]",
+        description: "get info about the current thread, or the threads of PID currently running on a vCPU \
+            (OSI can't list threads that aren't running)",
+        examples: &["threadinfo 1234"],
+    },
+    CommandHelp {
+        name: "procinfo",
+        usage: "procinfo [PID]",
+        description: "get info about the current process or PID: open files (descriptors 0-255 only), \
+            threads currently on a vCPU and the command line (only known for a process stopped in on entry)",
+        examples: &["procinfo", "procinfo 1234 --json"],
+    },
+    CommandHelp {
+        name: "proclist",
+        usage: "proclist [tree] [PID|NAME]",
+        description: "list running processes, optionally as a tree or only those matching a PID or name",
+        examples: &["proclist bash", "proclist tree", "proclist tree 1"],
+    },
+    CommandHelp {
+        name: "modules",
+        usage: "modules [PID]",
+        description: "list the modules loaded by the current process or PID, and loaded kernel modules",
+        examples: &["modules", "modules 1234 --json"],
+    },
+    CommandHelp {
+        name: "addr",
+        usage: "addr ADDR",
+        description: "show which module an address is in, as module+offset",
+        examples: &["addr 0x7ffff7a3b000", "addr $rip"],
+    },
+    CommandHelp {
+        name: "json",
+        usage: "json [on|off]",
+        description: "print JSON instead of tables from meminfo, modules, threadinfo, procinfo, proclist, check_taint and get_taint \
+            (add --json after one of them to print JSON just once)",
+        examples: &["json on", "proclist --json"],
+    },
+    CommandHelp {
+        name: "backtrace",
+        usage: "backtrace [N]",
+        description: "unwind up to N frames of the current stack, even without symbols",
+        examples: &["backtrace", "bt 5"],
+    },
+    CommandHelp {
+        name: "branch_history",
+        usage: "branch_history [N]",
+        description: "show the last N branches the debugged process took, for when the stack is too broken to unwind",
+        examples: &["branch_history", "branch_history 64"],
+    },
+    CommandHelp {
+        name: "search",
+        usage: "search <hex|str|utf16|ptr> PATTERN [in START END]",
+        description: "find a pattern in the process's mappings or a range",
+        examples: &["search str \"password\"", "search hex deadbeef in $rsp $rsp+0x1000", "search ptr 0x555555554000"],
+    },
+    CommandHelp {
+        name: "dump",
+        usage: "dump <START END|MAPPING> PATH",
+        description: "write a range or a named mapping (example: [heap]) to a host file",
+        examples: &["dump [heap] /tmp/heap.bin", "dump $rsp $rsp+0x100 /tmp/stack.bin"],
+    },
+    CommandHelp {
+        name: "phys_read",
+        usage: "phys_read ADDR LEN",
+        description: "hexdump physical memory",
+        examples: &["phys_read 0x1000 64"],
+    },
+    CommandHelp {
+        name: "v2p",
+        usage: "v2p ADDR",
+        description: "translate a virtual address to a physical address",
+        examples: &["v2p $rsp"],
+    },
+    CommandHelp {
+        name: "pagewalk",
+        usage: "pagewalk ADDR",
+        description: "show each level of the page table walk for an address (x86 only)",
+        examples: &["pagewalk 0x401000"],
+    },
+    CommandHelp {
+        name: "memview",
+        usage: "memview [pid PID|asid ASID|reset]",
+        description: "read memory through another process's page tables",
+        examples: &["memview pid 1234", "memview reset"],
+    },
+    CommandHelp {
+        name: "attach",
+        usage: "attach PID",
+        description: "debug another process from proclist, stopping once it is scheduled",
+        examples: &["attach 1234"],
+    },
+    CommandHelp {
+        name: "kmods",
+        usage: "kmods",
+        description: "list loaded kernel modules and how to load their symbols",
+        examples: &[],
+    },
+    CommandHelp {
+        name: "catch",
+        usage: "catch [EXCEPTION [proc]]",
+        description: "stop on a CPU exception, optionally only in the current process (catch list shows the names)",
+        examples: &["catch list", "catch pf proc", "catch 14"],
+    },
+    CommandHelp {
+        name: "uncatch",
+        usage: "uncatch EXCEPTION",
+        description: "stop catching an exception",
+        examples: &["uncatch pf"],
+    },
+    CommandHelp {
+        name: "catch_switch",
+        usage: "catch_switch [on|off]",
+        description: "stop whenever the current process is scheduled in or out",
+        examples: &["catch_switch on"],
+    },
+    CommandHelp {
+        name: "finish_kernel",
+        usage: "finish_kernel",
+        description: "on the next continue, run until the current process returns to user mode",
+        examples: &[],
+    },
+    CommandHelp {
+        name: "user_step",
+        usage: "user_step [on|off]",
+        description: "whether steps from user mode skip over kernel code (on by default)",
+        examples: &["user_step off"],
+    },
+    CommandHelp {
+        name: "bp",
+        usage: "bp ADDR [if COND]",
+        description: "break at ADDR when COND holds, checking the condition without stopping the guest",
+        examples: &["bp 0x401000 if rdi == 3 && *rsp != 0", "bp $rip+0x20"],
+    },
+    CommandHelp {
+        name: "log",
+        usage: "log ADDR ITEM... [if COND] [to PATH]",
+        description: "log registers/memory (rdi, *rsp, *rsi:16) each time ADDR runs, without stopping",
+        examples: &["log 0x401000 rdi rsi *rsi:16 if rdi != 0 to /tmp/calls.log"],
+    },
+    CommandHelp {
+        name: "trace",
+        usage: "trace ADDR ITEM... [if COND]",
+        description: "like log, but keep what is collected as trace frames",
+        examples: &["trace 0x401000 rdi *rsi:32"],
+    },
+    CommandHelp {
+        name: "tfind",
+        usage: "tfind [N|pc ADDR]",
+        description: "show collected trace frames, all of them or by number or address",
+        examples: &["tfind", "tfind 3", "tfind pc 0x401000"],
+    },
+    CommandHelp {
+        name: "tsave",
+        usage: "tsave PATH",
+        description: "write every collected trace frame to a JSON file",
+        examples: &["tsave /tmp/frames.json"],
+    },
+    CommandHelp {
+        name: "tclear",
+        usage: "tclear",
+        description: "discard collected trace frames",
+        examples: &[],
+    },
+    CommandHelp {
+        name: "bp_del",
+        usage: "bp_del ADDR",
+        description: "delete a breakpoint added with bp, log or trace",
+        examples: &["bp_del 0x401000"],
+    },
+    CommandHelp {
+        name: "bp_list",
+        usage: "bp_list",
+        description: "list breakpoints added with bp, log or trace and how often they were hit",
+        examples: &[],
+    },
+    CommandHelp {
+        name: "help",
+        usage: "help [COMMAND]",
+        description: "list commands, or show the usage of one with examples",
+        examples: &["help bp"],
+    },
+];
+
+/// Names of registers which may exist on this architecture. Only the ones PANDA can
+/// parse are shown.
+#[cfg(feature = "x86_64")]
+const REGISTER_NAMES: &[&str] = &[
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15", "rip",
+];
+
+#[cfg(feature = "i386")]
+const REGISTER_NAMES: &[&str] = &["eax", "ebx", "ecx", "edx", "esi", "edi", "ebp", "esp", "eip"];
+
+#[cfg(feature = "arm")]
+const REGISTER_NAMES: &[&str] = &[
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12",
+    "sp", "lr", "ip", "pc",
+];
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+const REGISTER_NAMES: &[&str] = &[
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+    "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra", "pc",
+];
+
+#[cfg(feature = "ppc")]
+const REGISTER_NAMES: &[&str] = &[
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12",
+    "r13", "r14", "r15", "r16", "r17", "r18", "r19", "r20", "r21", "r22", "r23",
+    "r24", "r25", "r26", "r27", "r28", "r29", "r30", "r31", "lr", "ctr", "pc",
+];
+
+fn register_names() -> Vec<&'static str> {
+    REGISTER_NAMES.iter()
+        .copied()
+        .filter(|name| name.parse::<Reg>().is_ok())
+        .collect()
+}
+
+pub(crate) fn print_overview(mut out: impl std::fmt::Write) {
+    outputln!(out);
+    outputln!(out, "Commands:");
+    for command in COMMANDS {
+        outputln!(out, "  {} - {}", command.usage, command.description);
+    }
+    outputln!(out);
+    outputln!(out, "Addresses can be numbers, registers or sums of them (example: $rsp+0x10).");
+    outputln!(out, "Separate commands with ; to run several at once. See help COMMAND for examples.");
+}
+
+pub(crate) fn print_command(name: &str, mut out: impl std::fmt::Write) {
+    let command = match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => command,
+        None => {
+            outputln!(out, "Unknown command {}", name);
+            print_suggestions(name, out);
+            return
+        }
+    };
+
+    outputln!(out);
+    outputln!(out, "Usage: {}", command.usage);
+    outputln!(out, "  {}", command.description);
+    if !command.examples.is_empty() {
+        outputln!(out);
+        outputln!(out, "Examples:");
+        for example in command.examples {
+            outputln!(out, "  monitor {}", example);
+        }
+    }
+    outputln!(out);
+}
+
+/// Suggest commands starting with what was typed, for a command that doesn't exist
+pub(crate) fn print_suggestions(typed: &str, mut out: impl std::fmt::Write) {
+    let matches: Vec<&str> = COMMANDS.iter()
+        .map(|command| command.name)
+        .filter(|name| !typed.is_empty() && name.starts_with(typed))
+        .collect();
+
+    if matches.is_empty() {
+        outputln!(out, "See help for a list of commands");
+    } else {
+        outputln!(out, "Did you mean: {}", matches.join(", "));
+    }
+}
+
+pub(crate) fn print_registers(mut out: impl std::fmt::Write) {
+    outputln!(out, "Registers: {}", register_names().join(", "));
+}
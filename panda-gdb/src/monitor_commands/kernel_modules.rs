@@ -0,0 +1,36 @@
+use gdbstub::outputln;
+use tabwriter::{TabWriter, Alignment};
+
+use std::io::Write;
+
+use crate::backend::Guest;
+
+pub(crate) fn print(guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
+    let modules = guest.kernel_modules();
+
+    outputln!(out);
+
+    let output = Vec::new();
+    let mut output = TabWriter::new(output).padding(1).alignment(Alignment::Right);
+
+    let _ = writeln!(output, "Base\tSize\tModule");
+    let _ = writeln!(output, "====\t====\t======");
+
+    #[allow(unused_must_use)]
+    for module in &modules {
+        writeln!(output, "{:#x}\t{:#x}\t{}", module.base, module.size, module.short_name());
+    }
+
+    let _ = output.flush();
+    let output = String::from_utf8(output.into_inner().unwrap()).unwrap();
+    outputln!(out, "{}", output);
+
+    // gdbstub can't report libraries itself, so give the commands to load them by hand
+    if !modules.is_empty() {
+        outputln!(out, "To load symbols for a module:");
+        for module in &modules {
+            outputln!(out, "  add-symbol-file {}.ko {:#x}", module.short_name(), module.base);
+        }
+        outputln!(out);
+    }
+}
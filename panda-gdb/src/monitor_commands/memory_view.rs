@@ -0,0 +1,41 @@
+use gdbstub::outputln;
+
+use super::parser::ViewTarget;
+use crate::backend::Guest;
+
+pub(crate) fn switch(guest: &mut dyn Guest, target: Option<ViewTarget>, mut out: impl std::fmt::Write) {
+    if !cfg!(any(feature = "x86_64", feature = "i386")) {
+        outputln!(out, "Switching the memory view is only supported on x86");
+        return
+    }
+
+    let procs = guest.processes();
+    let proc = match target {
+        None => {
+            match guest.memory_view() {
+                Some(asid) => outputln!(out, "Memory reads use the address space of ASID {:#x}", asid),
+                None => outputln!(out, "Memory reads use the current process"),
+            }
+            return
+        }
+        Some(ViewTarget::Reset) => {
+            guest.set_memory_view(None);
+            outputln!(out, "Memory reads use the current process again");
+            return
+        }
+        Some(ViewTarget::Pid(pid)) => procs.iter().find(|proc| proc.pid as u64 == pid),
+        Some(ViewTarget::Asid(asid)) => procs.iter().find(|proc| proc.asid as u64 == asid),
+    };
+
+    match proc {
+        Some(proc) => {
+            guest.set_memory_view(Some(proc.asid));
+            outputln!(
+                out,
+                "Memory reads now use the address space of {} (PID {}, ASID {:#x}) until `memview reset`",
+                proc.name, proc.pid, proc.asid
+            );
+        }
+        None => outputln!(out, "No such process, see proclist"),
+    }
+}
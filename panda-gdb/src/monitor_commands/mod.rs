@@ -1,7 +1,13 @@
-use panda::prelude::*;
-use panda::taint;
+use panda::regs::Reg;
 
 use gdbstub::outputln;
+use peg::{error::ParseError, str::LineCol};
+use serde::Serialize;
+
+use std::cell::RefCell;
+
+use crate::backend::Guest;
+use crate::memory_map::Mapping;
 
 mod parser;
 use parser::{Command, TaintTarget};
@@ -9,94 +15,205 @@ use parser::{Command, TaintTarget};
 mod thread_info;
 mod proc_info;
 mod proc_list;
+mod modules;
+mod backtrace;
+mod branch_history;
+mod search;
+mod dump;
+mod physical;
+mod memory_view;
+mod kernel_modules;
+mod catch;
+mod stepping;
+mod bp;
+mod trace;
+mod help;
 
-pub(crate) fn handle_command(cmd: &str, cpu: &mut CPUState, mut out: impl std::fmt::Write) {
+pub(crate) fn handle_command(cmd: &str, guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
     let cmd = cmd.trim();
+
+    // Addresses can be given relative to registers, which are read while parsing
+    let guest = RefCell::new(guest);
+    let read_register = |reg: Reg| guest.borrow_mut().register(reg);
+
     // this parsing is totally fine™
-    match Command::parse(cmd) {
-        Ok(Command::Taint(target, label)) => {
+    let commands = match Command::parse(cmd, &read_register) {
+        Ok(commands) => commands,
+        Err(err) => {
+            print_parse_error(cmd, err, out);
+            return
+        }
+    };
+
+    let guest = guest.into_inner();
+    for (command, json) in commands {
+        run_command(command, json, guest, &mut out);
+    }
+}
+
+fn run_command(command: Command, json: bool, guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
+    if json && !command.supports_json() {
+        outputln!(out, "Note: this command has no JSON output, ignoring --json");
+    }
+
+    // Commands printing tables can print the same data as JSON for scripts instead
+    let json = json || guest.json_output();
+    match command {
+        Command::Taint(target, label) => {
             match target {
                 TaintTarget::Address(addr) => {
-                    let addr = panda::mem::virt_to_phys(cpu, addr);
-                    taint::label_ram(addr, label);
+                    let addr = guest.taint_address(addr, label);
                     outputln!(out, "Memory location {:#x?} tainted.", addr);
                 }
                 TaintTarget::Register(reg) => {
-                    taint::label_reg(reg, label);
-                    outputln!(out, "Register {} tainted.", reg.to_string());
+                    guest.taint_register(reg, label);
+                    outputln!(out, "Register {} tainted.", reg);
                 }
             }
         },
-        Ok(Command::CheckTaint(target)) => {
-            match target {
-                TaintTarget::Address(addr) => {
-                    let addr = panda::mem::virt_to_phys(cpu, addr);
-                    outputln!(out, "{:?}", taint::check_ram(addr));
-                }
-                TaintTarget::Register(reg) => {
-                    outputln!(out, "{:?}", taint::check_reg(reg));
-                }
-            }
-        },
-        Ok(Command::GetTaint(target)) => {
-            match target {
-                TaintTarget::Address(addr) => {
-                    let addr = panda::mem::virt_to_phys(cpu, addr);
-                    // TODO: fix segfault
-                    if taint::check_ram(addr) {
-                        outputln!(out, "{:?}", taint::get_ram(addr));
-                    } else {
-                        outputln!(out, "[]")
-                    }
-                }
-                TaintTarget::Register(reg) => {
-                    // TODO: fix segfault
-                    if taint::check_reg(reg) {
-                        outputln!(out, "{:?}", taint::get_reg(reg));
-                    } else {
-                        outputln!(out, "[]")
-                    }
-                }
+        Command::CheckTaint(target) => {
+            let tainted = match &target {
+                TaintTarget::Address(addr) => guest.address_tainted(*addr),
+                TaintTarget::Register(reg) => guest.register_tainted(*reg),
+            };
+
+            if json {
+                print_json(&TaintJson { target: target.to_string(), tainted: Some(tainted), labels: None }, out);
+            } else {
+                outputln!(out, "{:?}", tainted);
             }
         },
-        Ok(Command::MemInfo) => crate::memory_map::print_to_gdb(cpu, out),
-        Ok(Command::ThreadInfo) => thread_info::print(cpu, out),
-        Ok(Command::ProcInfo) => proc_info::print(cpu, out),
-        Ok(Command::ProcList) => proc_list::print(cpu, out),
-        Ok(Command::Help) => print_help_text(out),
-        Err(peg::error::ParseError { location, expected }) => {
-            outputln!(out);
-            outputln!(out, "Error:");
-            outputln!(out, "    {}", cmd);
-            let expected: Vec<&str> = expected.tokens().collect();
-            if let &[expected] = &expected[..] {
-                outputln!(
-                    out,
-                    "   {}^------ Invalid syntax, expected {}",
-                    " ".repeat(location.column),
-                    expected
-                );
+        Command::GetTaint(target) => {
+            let labels = match &target {
+                TaintTarget::Address(addr) => guest.address_labels(*addr),
+                TaintTarget::Register(reg) => guest.register_labels(*reg),
+            };
+
+            if json {
+                print_json(&TaintJson { target: target.to_string(), tainted: None, labels: Some(labels) }, out);
             } else {
-                outputln!(
-                    out,
-                    "   {}^------ Invalid syntax, expected one of the following: {}",
-                    " ".repeat(location.column),
-                    expected.join(", ")
-                );
+                outputln!(out, "{:?}", labels);
             }
-            outputln!(out);
+        },
+        Command::MemInfo if json => {
+            let mappings = guest.mappings();
+            let mappings: Vec<_> = mappings.iter().map(Mapping::to_json).collect();
+            print_json(&mappings, out)
+        }
+        Command::MemInfo => crate::memory_map::print_to_gdb(&guest.mappings(), out),
+        Command::ThreadInfo(pid) if json => thread_info::print_json(guest, pid, out),
+        Command::ThreadInfo(pid) => thread_info::print(guest, pid, out),
+        Command::ProcInfo(pid) if json => proc_info::print_json(guest, pid, out),
+        Command::ProcInfo(pid) => proc_info::print(guest, pid, out),
+        Command::ProcList(filter, _) if json => proc_list::print_json(guest, filter, out),
+        Command::ProcList(filter, true) => proc_list::print_tree(guest, filter, out),
+        Command::ProcList(filter, false) => proc_list::print(guest, filter, out),
+        Command::Modules(pid) if json => modules::print_json(guest, pid, out),
+        Command::Modules(pid) => modules::print(guest, pid, out),
+        Command::Address(addr) => modules::print_address(guest, addr, out),
+        Command::Json(enabled) => json_mode(guest, enabled, out),
+        Command::Help(None) => help::print_overview(out),
+        Command::Help(Some(command)) => help::print_command(&command, out),
+        Command::Backtrace(count) => {
+            backtrace::print(guest, count.unwrap_or(backtrace::DEFAULT_MAX_FRAMES), out)
+        }
+        Command::BranchHistory(count) => {
+            branch_history::print(guest, count.unwrap_or(branch_history::DEFAULT_COUNT), out)
+        }
+        Command::Search(pattern, range) => search::print(guest, pattern, range, out),
+        Command::Dump(target, path) => dump::dump(guest, target, &path, out),
+        Command::PhysRead(addr, len) => physical::read(guest, addr, len, out),
+        Command::VirtToPhys(addr) => physical::virt_to_phys(guest, addr, out),
+        Command::PageWalk(addr) => physical::page_walk(guest, addr, out),
+        Command::MemoryView(target) => memory_view::switch(guest, target, out),
+        Command::Attach(pid) => match guest.attach(pid as _) {
+            Some(name) => outputln!(out, "Attaching to {} (PID {}), continue to stop once it runs", name, pid),
+            None => outputln!(out, "No such process, see proclist"),
+        },
+        Command::KernelModules => kernel_modules::print(guest, out),
+        Command::Catch(exception, current_process) => {
+            catch::add(guest, exception, current_process, out)
         }
+        Command::Uncatch(exception) => catch::remove(guest, exception, out),
+        Command::CatchList => catch::list(guest, out),
+        Command::CatchSwitch(enabled) => catch::switches(guest, enabled, out),
+        Command::FinishKernel => stepping::finish_kernel(guest, out),
+        Command::UserStep(enabled) => stepping::user_only(guest, enabled, out),
+        Command::Breakpoint(addr, condition) => bp::add(guest, addr, condition, out),
+        Command::DeleteBreakpoint(addr) => bp::remove(guest, addr, out),
+        Command::LogPoint(addr, items, condition, path) => {
+            bp::add_log(guest, addr, items, condition, path, out)
+        }
+        Command::ListBreakpoints => bp::list(guest, out),
+        Command::Trace(addr, items, condition) => bp::add_trace(guest, addr, items, condition, out),
+        Command::TraceFind(query) => trace::find(guest, query, out),
+        Command::TraceSave(path) => trace::save(guest, &path, out),
+        Command::TraceClear => trace::clear(guest, out),
     }
 }
 
-fn print_help_text(mut out: impl std::fmt::Write) {
+fn print_parse_error(cmd: &str, err: ParseError<LineCol>, mut out: impl std::fmt::Write) {
+    let ParseError { location, expected } = err;
+
+    outputln!(out);
+    outputln!(out, "Error:");
+    outputln!(out, "    {}", cmd);
+    let expected: Vec<&str> = expected.tokens().collect();
+    if let &[expected] = &expected[..] {
+        outputln!(
+            out,
+            "   {}^------ Invalid syntax, expected {}",
+            " ".repeat(location.column),
+            expected
+        );
+    } else {
+        outputln!(
+            out,
+            "   {}^------ Invalid syntax, expected one of the following: {}",
+            " ".repeat(location.column),
+            expected.join(", ")
+        );
+    }
+
+    // The command name itself is wrong
+    let before = cmd[..location.offset].trim_end();
+    if before.is_empty() || before.ends_with(';') {
+        let typed = cmd[location.offset..].split(|c: char| c.is_whitespace() || c == ';').next().unwrap_or("");
+        help::print_suggestions(typed, &mut out);
+    }
+
+    if expected.iter().any(|token| token.contains("register") || token.contains("address")) {
+        help::print_registers(&mut out);
+    }
     outputln!(out);
-    outputln!(out, "Commands:");
-    outputln!(out, "  meminfo - print out the current memory map");
-    outputln!(out, "  taint - apply taint to a given register/memory location");
-    outputln!(out, "  check_taint - check if a given register/memory location is tainted");
-    outputln!(out, "  get_taint - get the taint labels for a given register/memory location");
-    outputln!(out, "  threadinfo - get info about threads of the current process");
-    outputln!(out, "  procinfo - get info about the current process");
-    outputln!(out, "  proclist - list all the currently running processes");
+}
+
+/// Result of `check_taint` or `get_taint` as JSON
+#[derive(Serialize)]
+struct TaintJson {
+    target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tainted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<u32>>,
+}
+
+/// Print a value as a single line of JSON
+fn print_json(value: &impl Serialize, mut out: impl std::fmt::Write) {
+    match serde_json::to_string(value) {
+        Ok(json) => outputln!(out, "{}", json),
+        Err(err) => outputln!(out, "Failed to convert output to JSON: {}", err),
+    }
+}
+
+fn json_mode(guest: &mut dyn Guest, enabled: Option<bool>, mut out: impl std::fmt::Write) {
+    if let Some(enabled) = enabled {
+        guest.set_json_output(enabled);
+    }
+
+    if guest.json_output() {
+        outputln!(out, "Commands print JSON where supported");
+    } else {
+        outputln!(out, "Commands print tables, add --json to print JSON");
+    }
 }
@@ -0,0 +1,165 @@
+use panda::prelude::*;
+
+use gdbstub::outputln;
+use serde::Serialize;
+use tabwriter::{TabWriter, Alignment};
+
+use std::io::Write;
+
+use crate::backend::{Guest, Process};
+use crate::memory_map::{self, Mapping};
+
+/// A module as printed by `modules --json`
+#[derive(Serialize)]
+struct ModuleJson<'a> {
+    base: String,
+    size: String,
+    name: &'a str,
+    path: Option<&'a str>,
+    permissions: &'a str,
+}
+
+impl<'a> ModuleJson<'a> {
+    fn new((module, permissions): &'a (Mapping, String)) -> Self {
+        ModuleJson {
+            base: format!("{:#x}", module.base),
+            size: format!("{:#x}", module.size),
+            name: module.short_name(),
+            path: module.file.as_deref(),
+            permissions,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ModulesJson<'a> {
+    pid: u64,
+    modules: Vec<ModuleJson<'a>>,
+    kernel_modules: Vec<ModuleJson<'a>>,
+}
+
+/// Modules, each with the permissions of its mappings
+type Modules = Vec<(Mapping, String)>;
+
+/// The current process or the one with the given PID, along with its modules
+fn process_modules(guest: &mut dyn Guest, pid: Option<u64>) -> Option<(Process, Modules)> {
+    let proc = match pid {
+        Some(pid) => guest.processes().into_iter().find(|proc| proc.pid as u64 == pid)?,
+        None => guest.current_process(),
+    };
+    let mappings = guest.process_mappings(proc.pid)?;
+    let modules = with_permissions(guest, proc.asid, &mappings, memory_map::modules(&mappings));
+
+    Some((proc, modules))
+}
+
+/// Kernel modules, walking the page tables of the current process since the kernel is
+/// mapped in every one
+fn kernel_modules(guest: &mut dyn Guest) -> Modules {
+    let asid = guest.asid();
+
+    guest.kernel_modules()
+        .into_iter()
+        .map(|module| {
+            let permissions = page_permissions(guest, asid, module.base);
+            (module, permissions)
+        })
+        .collect()
+}
+
+/// Pair each module with the permissions of each of its mappings, since OSI doesn't
+/// report them
+fn with_permissions(
+    guest: &mut dyn Guest,
+    asid: target_ulong,
+    mappings: &[Mapping],
+    modules: Vec<Mapping>,
+) -> Modules {
+    modules.into_iter()
+        .map(|module| {
+            let permissions: Vec<String> = mappings.iter()
+                .filter(|mapping| mapping.file == module.file && module.contains(mapping.base))
+                .map(|mapping| page_permissions(guest, asid, mapping.base))
+                .collect();
+
+            (module, permissions.join(","))
+        })
+        .collect()
+}
+
+/// Permissions of the page at `addr`: `---` if it isn't present, and `???` on
+/// architectures whose page tables can't be walked
+fn page_permissions(guest: &mut dyn Guest, asid: target_ulong, addr: target_ptr_t) -> String {
+    match guest.page_walk_in(asid, addr) {
+        Some(walk) => walk.permissions().unwrap_or_else(|| "---".to_owned()),
+        None => "???".to_owned(),
+    }
+}
+
+fn print_table(modules: &[(Mapping, String)], mut out: impl std::fmt::Write) {
+    let output = Vec::new();
+    let mut output = TabWriter::new(output).padding(1).alignment(Alignment::Right);
+
+    let _ = writeln!(output, "Base\tSize\tPerms\tModule");
+    let _ = writeln!(output, "====\t====\t=====\t======");
+
+    #[allow(unused_must_use)]
+    for (module, permissions) in modules {
+        writeln!(output, "{:#x}\t{:#x}\t{}\t{}", module.base, module.size, permissions, module.display_name());
+    }
+
+    let _ = output.flush();
+    let output = String::from_utf8(output.into_inner().unwrap()).unwrap();
+    outputln!(out, "{}", output);
+}
+
+pub(crate) fn print(guest: &mut dyn Guest, pid: Option<u64>, mut out: impl std::fmt::Write) {
+    let (proc, modules) = match process_modules(guest, pid) {
+        Some(modules) => modules,
+        None => {
+            outputln!(out, "No such process, see proclist");
+            return
+        }
+    };
+
+    outputln!(out);
+    outputln!(out, "Modules of {} (PID {}):", proc.name, proc.pid);
+    print_table(&modules, &mut out);
+
+    let kernel_modules = kernel_modules(guest);
+    if kernel_modules.is_empty() {
+        outputln!(out, "No kernel modules loaded");
+    } else {
+        outputln!(out, "Kernel modules:");
+        print_table(&kernel_modules, &mut out);
+    }
+    outputln!(out);
+}
+
+pub(crate) fn print_json(guest: &mut dyn Guest, pid: Option<u64>, mut out: impl std::fmt::Write) {
+    let (proc, modules) = match process_modules(guest, pid) {
+        Some(modules) => modules,
+        None => {
+            outputln!(out, "No such process, see proclist");
+            return
+        }
+    };
+    let kernel_modules = kernel_modules(guest);
+
+    super::print_json(&ModulesJson {
+        pid: proc.pid as u64,
+        modules: modules.iter().map(ModuleJson::new).collect(),
+        kernel_modules: kernel_modules.iter().map(ModuleJson::new).collect(),
+    }, out);
+}
+
+/// Print an address as `module+offset`, looking in the current process then the kernel
+pub(crate) fn print_address(guest: &mut dyn Guest, addr: target_ptr_t, mut out: impl std::fmt::Write) {
+    let location = memory_map::symbolize(&guest.mappings(), addr)
+        .or_else(|| memory_map::symbolize(&guest.kernel_modules(), addr));
+
+    match location {
+        Some(location) => outputln!(out, "{:#x} is {}", addr, location),
+        None => outputln!(out, "{:#x} isn't in any mapping of the current process or kernel module", addr),
+    }
+}
@@ -3,20 +3,68 @@ use panda::regs::Reg;
 
 use peg::{str::LineCol, error::ParseError};
 
+use crate::breakpoints::{CmpOp, Comparison, Condition, LogItem, Operand};
+
 pub(crate) enum Command {
     Taint(TaintTarget, u32),
     CheckTaint(TaintTarget),
     GetTaint(TaintTarget),
-    Help,
+    Help(Option<String>),
     MemInfo,
-    ThreadInfo,
-    ProcInfo,
-    ProcList,
+    ThreadInfo(Option<u64>),
+    ProcInfo(Option<u64>),
+    /// Filter, and whether to show the parent/child tree
+    ProcList(Option<ProcFilter>, bool),
+    Modules(Option<u64>),
+    Address(target_ptr_t),
+    Backtrace(Option<usize>),
+    BranchHistory(Option<usize>),
+    Search(SearchPattern, Option<(target_ptr_t, target_ptr_t)>),
+    Dump(DumpTarget, String),
+    PhysRead(target_ptr_t, usize),
+    VirtToPhys(target_ptr_t),
+    PageWalk(target_ptr_t),
+    MemoryView(Option<ViewTarget>),
+    Attach(u64),
+    KernelModules,
+    Catch(i32, bool),
+    Uncatch(i32),
+    CatchList,
+    CatchSwitch(Option<bool>),
+    FinishKernel,
+    UserStep(Option<bool>),
+    Breakpoint(target_ptr_t, Option<Condition>),
+    DeleteBreakpoint(target_ptr_t),
+    LogPoint(target_ptr_t, Vec<LogItem>, Option<Condition>, Option<String>),
+    ListBreakpoints,
+    Trace(target_ptr_t, Vec<LogItem>, Option<Condition>),
+    TraceFind(Option<TraceQuery>),
+    TraceSave(String),
+    TraceClear,
+    Json(Option<bool>),
 }
 
+/// Reads a register of the stopped vCPU, so addresses can be given relative to one
+pub(crate) type ReadRegister = dyn Fn(Reg) -> Option<u64>;
+
 impl Command {
-    pub(crate) fn parse(cmd: &str) -> Result<Self, ParseError<LineCol>> {
-        monitor_commands::command(cmd)
+    /// Parse commands separated by `;`, along with whether `--json` was given after each
+    pub(crate) fn parse(cmd: &str, registers: &ReadRegister) -> Result<Vec<(Self, bool)>, ParseError<LineCol>> {
+        monitor_commands::command_line(cmd, registers)
+    }
+
+    /// Whether the command can print its output as JSON
+    pub(crate) fn supports_json(&self) -> bool {
+        matches!(
+            self,
+            Command::CheckTaint(_)
+                | Command::GetTaint(_)
+                | Command::MemInfo
+                | Command::ThreadInfo(_)
+                | Command::ProcInfo(_)
+                | Command::ProcList(..)
+                | Command::Modules(_)
+        )
     }
 }
 
@@ -25,9 +73,53 @@ pub(crate) enum TaintTarget {
     Register(Reg),
 }
 
+impl std::fmt::Display for TaintTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaintTarget::Address(addr) => write!(f, "*{:#x}", addr),
+            TaintTarget::Register(reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+pub(crate) enum ProcFilter {
+    Pid(u64),
+    /// Part of the process name
+    Name(String),
+}
+
+pub(crate) enum SearchPattern {
+    Bytes(Vec<u8>),
+    Ascii(String),
+    Utf16(String),
+    Pointer(target_ptr_t),
+}
+
+pub(crate) enum DumpTarget {
+    Range(target_ptr_t, target_ptr_t),
+    Mapping(String),
+}
+
+pub(crate) enum TraceQuery {
+    Frame(usize),
+    Pc(target_ptr_t),
+}
+
+pub(crate) enum ViewTarget {
+    Pid(u64),
+    Asid(u64),
+    Reset,
+}
+
 peg::parser!{
-    grammar monitor_commands() for str {
-        pub(crate) rule command() -> Command
+    grammar monitor_commands(registers: &ReadRegister) for str {
+        pub(crate) rule command_line() -> Vec<(Command, bool)>
+            = _? commands:(single_command() ++ (_? ";" _?)) _? ";"? _? { commands }
+
+        rule single_command() -> (Command, bool)
+            = command:command() json:(_ "--json")? { (command, json.is_some()) }
+
+        rule command() -> Command
             = taint()
             / check_taint()
             / get_taint()
@@ -35,22 +127,256 @@ peg::parser!{
             / proc_info()
             / proc_list()
             / thread_info()
+            / modules()
+            / address_info()
+            / backtrace()
+            / branch_history()
+            / search()
+            / dump()
+            / phys_read()
+            / virt_to_phys()
+            / page_walk()
+            / memory_view()
+            / attach()
+            / kernel_modules()
+            / catch_switch()
+            / catch()
+            / uncatch()
+            / finish_kernel()
+            / user_step()
+            / delete_breakpoint()
+            / list_breakpoints()
+            / log_point()
+            / trace()
+            / trace_find()
+            / trace_save()
+            / trace_clear()
+            / breakpoint()
+            / json()
             / help()
 
         rule help() -> Command
-            = "help" { Command::Help }
+            = "help" command:(_ command:word() { command })? { Command::Help(command) }
 
         rule mem_info() -> Command
             = "meminfo" { Command::MemInfo }
 
         rule proc_info() -> Command
-            = "procinfo" { Command::ProcInfo }
+            = "procinfo" pid:(_ pid:number() { pid })? { Command::ProcInfo(pid) }
 
         rule proc_list() -> Command
-            = "proclist" { Command::ProcList }
+            = "proclist" tree:(_ "tree")? filter:(_ filter:proc_filter() { filter })? {
+                Command::ProcList(filter, tree.is_some())
+            }
+
+        rule proc_filter() -> ProcFilter
+            = pid:number() { ProcFilter::Pid(pid) }
+            / !"--" name:word() { ProcFilter::Name(name) }
 
         rule thread_info() -> Command
-            = "threadinfo" { Command::ThreadInfo }
+            = "threadinfo" pid:(_ pid:number() { pid })? { Command::ThreadInfo(pid) }
+
+        rule modules() -> Command
+            = "modules" pid:(_ pid:number() { pid })? { Command::Modules(pid) }
+
+        rule address_info() -> Command
+            = "addr" _ addr:address() { Command::Address(addr as target_ptr_t) }
+
+        rule backtrace() -> Command
+            = ("backtrace" / "bt") count:(_ count:number() { count as usize })? {
+                Command::Backtrace(count)
+            }
+
+        rule branch_history() -> Command
+            = "branch_history" count:(_ count:number() { count as usize })? {
+                Command::BranchHistory(count)
+            }
+
+        rule search() -> Command
+            = "search" _ pattern:search_pattern() range:(_ "in" _ start:address() _ end:address() {
+                (start as target_ptr_t, end as target_ptr_t)
+            })? {
+                Command::Search(pattern, range)
+            }
+
+        rule search_pattern() -> SearchPattern
+            = quiet!{
+                "hex" _ bytes:hex_bytes() { SearchPattern::Bytes(bytes) }
+                / "str" _ string:quoted_string() { SearchPattern::Ascii(string) }
+                / "utf16" _ string:quoted_string() { SearchPattern::Utf16(string) }
+                / "ptr" _ ptr:address() { SearchPattern::Pointer(ptr as target_ptr_t) }
+            }
+            / expected!("a pattern (example: hex deadbeef, str \"abc\", utf16 \"abc\", ptr 0x55555555)")
+
+        rule hex_bytes() -> Vec<u8>
+            = hex:$(['0'..='9' | 'a'..='f' | 'A'..='F']+) {?
+                if hex.len() % 2 == 0 {
+                    (0..hex.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| "invalid hex bytes")
+                } else {
+                    Err("an even number of hex digits")
+                }
+            }
+
+        rule quoted_string() -> String
+            = "\"" string:$((!['"'] [_])+) "\"" { string.to_owned() }
+
+        rule dump() -> Command
+            = "dump" _ target:dump_target() _ path:word() { Command::Dump(target, path) }
+
+        rule dump_target() -> DumpTarget
+            = start:address() _ end:address() {
+                DumpTarget::Range(start as target_ptr_t, end as target_ptr_t)
+            }
+            / name:word() { DumpTarget::Mapping(name) }
+
+        rule word() -> String
+            = quiet!{ word:$((!(_ / ";") [_])+) { word.to_owned() } }
+            / expected!("a name or path")
+
+        rule phys_read() -> Command
+            = "phys_read" _ addr:address() _ len:number() {
+                Command::PhysRead(addr as target_ptr_t, len as usize)
+            }
+
+        rule virt_to_phys() -> Command
+            = "v2p" _ addr:address() { Command::VirtToPhys(addr as target_ptr_t) }
+
+        rule page_walk() -> Command
+            = "pagewalk" _ addr:address() { Command::PageWalk(addr as target_ptr_t) }
+
+        rule memory_view() -> Command
+            = "memview" target:(_ target:view_target() { target })? { Command::MemoryView(target) }
+
+        rule view_target() -> ViewTarget
+            = quiet!{
+                "pid" _ pid:number() { ViewTarget::Pid(pid) }
+                / "asid" _ asid:number() { ViewTarget::Asid(asid) }
+                / "reset" { ViewTarget::Reset }
+            }
+            / expected!("pid PID, asid ASID or reset")
+
+        rule attach() -> Command
+            = "attach" _ pid:number() { Command::Attach(pid) }
+
+        rule kernel_modules() -> Command
+            = "kmods" { Command::KernelModules }
+
+        rule catch_switch() -> Command
+            = "catch_switch" enabled:(_ enabled:on_off() { enabled })? {
+                Command::CatchSwitch(enabled)
+            }
+
+        rule on_off() -> bool
+            = quiet!{ "on" { true } / "off" { false } }
+            / expected!("on or off")
+
+        rule json() -> Command
+            = "json" enabled:(_ enabled:on_off() { enabled })? { Command::Json(enabled) }
+
+        rule finish_kernel() -> Command
+            = "finish_kernel" { Command::FinishKernel }
+
+        rule user_step() -> Command
+            = "user_step" enabled:(_ enabled:on_off() { enabled })? {
+                Command::UserStep(enabled)
+            }
+
+        rule catch() -> Command
+            = "catch" _ exception:exception() current_process:(_ "proc")? {
+                Command::Catch(exception, current_process.is_some())
+            }
+            / "catch" (_ "list")? { Command::CatchList }
+
+        rule uncatch() -> Command
+            = "uncatch" _ exception:exception() { Command::Uncatch(exception) }
+
+        rule exception() -> i32
+            = quiet!{
+                index:number() { index as i32 }
+                / name:$(['a'..='z' | '_'] ['a'..='z' | '0'..='9' | '_']*) {?
+                    crate::exceptions::by_name(name)
+                        .ok_or("unknown exception name")
+                }
+            }
+            / expected!("an exception number or name (see catch list)")
+
+        rule breakpoint() -> Command
+            = "bp" _ addr:address() condition:(_ "if" _ condition:condition() { condition })? {
+                Command::Breakpoint(addr as target_ptr_t, condition)
+            }
+
+        rule delete_breakpoint() -> Command
+            = "bp_del" _ addr:address() { Command::DeleteBreakpoint(addr as target_ptr_t) }
+
+        rule list_breakpoints() -> Command
+            = "bp_list" { Command::ListBreakpoints }
+
+        rule log_point() -> Command
+            = "log" _ addr:address() items:(_ item:log_item() { item })+
+                condition:(_ "if" _ condition:condition() { condition })?
+                path:(_ "to" _ path:word() { path })?
+            {
+                Command::LogPoint(addr as target_ptr_t, items, condition, path)
+            }
+
+        rule trace() -> Command
+            = "trace" _ addr:address() items:(_ item:log_item() { item })+
+                condition:(_ "if" _ condition:condition() { condition })?
+            {
+                Command::Trace(addr as target_ptr_t, items, condition)
+            }
+
+        rule trace_find() -> Command
+            = "tfind" query:(_ query:trace_query() { query })? { Command::TraceFind(query) }
+
+        rule trace_query() -> TraceQuery
+            = quiet!{
+                "pc" _ pc:address() { TraceQuery::Pc(pc as target_ptr_t) }
+                / frame:number() { TraceQuery::Frame(frame as usize) }
+            }
+            / expected!("a frame number or pc ADDR")
+
+        rule trace_save() -> Command
+            = "tsave" _ path:word() { Command::TraceSave(path) }
+
+        rule trace_clear() -> Command
+            = "tclear" { Command::TraceClear }
+
+        rule log_item() -> LogItem
+            = quiet!{
+                "*" addr:operand() ":" len:number() { LogItem::Bytes(addr, len as usize) }
+                / !(("if" / "to") _) operand:operand() { LogItem::Value(operand) }
+            }
+            / expected!("a register, *ADDR or *ADDR:LEN")
+
+        rule condition() -> Condition
+            = comparisons:(comparison() ++ (_? "&&" _?)) { Condition(comparisons) }
+
+        rule comparison() -> Comparison
+            = lhs:operand() _? op:cmp_op() _? rhs:operand() { Comparison { lhs, op, rhs } }
+
+        rule cmp_op() -> CmpOp
+            = quiet!{
+                "==" { CmpOp::Eq }
+                / "!=" { CmpOp::Ne }
+                / "<=" { CmpOp::Le }
+                / ">=" { CmpOp::Ge }
+                / "<" { CmpOp::Lt }
+                / ">" { CmpOp::Gt }
+            }
+            / expected!("a comparison (==, !=, <, <=, >, >=)")
+
+        rule operand() -> Operand
+            = quiet!{
+                "*" operand:operand() { Operand::Deref(Box::new(operand)) }
+                / num:number() { Operand::Number(num) }
+                / "$"? reg:register() { Operand::Register(reg) }
+            }
+            / expected!("a number, a register or *ADDR")
 
         rule taint() -> Command
             = "taint" _ target:taint_target() _ label:number() {
@@ -59,8 +385,8 @@ peg::parser!{
 
         rule taint_target() -> TaintTarget
             = quiet!{
-                "*" addr:number() { TaintTarget::Address(addr) }
-                / reg:register() { TaintTarget::Register(reg) }
+                "*" addr:address() { TaintTarget::Address(addr) }
+                / "$"? reg:register() { TaintTarget::Register(reg) }
             }
             / expected!("an address (example: *0x55555555) or a register name")
 
@@ -70,11 +396,29 @@ peg::parser!{
         rule get_taint() -> Command
             = "get_taint" _ target:taint_target() { Command::GetTaint(target) }
 
-        // TODO: display available registers on error
+        // Valid registers are listed by `handle_command` when this fails
         rule register() -> Reg
             = reg:$(['a'..='z' | 'A'..='Z'] ['a'..='z' | 'A'..='Z' | '0'..='9']*) {?
                 reg.parse()
-                    .map_err(|_| "invalid register name")
+                    .map_err(|_| "a register name")
+            }
+
+        /// A number, a register or a sum of them, evaluated when the command is parsed
+        rule address() -> u64
+            = quiet!{
+                first:address_term() rest:(_? op:$("+" / "-") _? term:address_term() { (op, term) })* {
+                    rest.into_iter().fold(first, |addr, (op, term)| match op {
+                        "+" => addr.wrapping_add(term),
+                        _ => addr.wrapping_sub(term),
+                    })
+                }
+            }
+            / expected!("an address (example: 0x401000, $rsp+0x10)")
+
+        rule address_term() -> u64
+            = number()
+            / "$"? reg:register() {?
+                registers(reg).ok_or("a register of the stopped CPU")
             }
 
         rule number() -> u64
@@ -93,3 +437,23 @@ peg::parser!{
         rule _() = quiet!{ [' ' | '\n' | '\t']+ }
     }
 }
+
+#[cfg(all(test, feature = "x86_64"))]
+mod tests {
+    use super::Command;
+
+    fn parse(cmd: &str) -> Command {
+        let mut commands = Command::parse(cmd, &|_| None)
+            .unwrap_or_else(|_| panic!("{} didn't parse", cmd));
+
+        commands.remove(0).0
+    }
+
+    #[test]
+    fn exception_names_with_digits() {
+        assert!(matches!(parse("catch int80"), Command::Catch(0x80, false)));
+        assert!(matches!(parse("catch int80 proc"), Command::Catch(0x80, true)));
+        assert!(matches!(parse("uncatch int80"), Command::Uncatch(0x80)));
+        assert!(matches!(parse("catch pf"), Command::Catch(14, false)));
+    }
+}
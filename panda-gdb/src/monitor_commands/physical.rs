@@ -0,0 +1,75 @@
+use panda::prelude::*;
+
+use gdbstub::outputln;
+
+use crate::backend::Guest;
+
+/// Most bytes `phys_read` prints, as every byte ends up in GDB's console
+const MAX_READ_LEN: usize = 0x10000;
+
+pub(crate) fn read(guest: &mut dyn Guest, addr: target_ptr_t, len: usize, mut out: impl std::fmt::Write) {
+    if len > MAX_READ_LEN {
+        outputln!(out, "Reads are limited to {:#x} bytes, use dump for more", MAX_READ_LEN);
+        return
+    }
+
+    match guest.read_physical(addr, len) {
+        Some(bytes) => {
+            outputln!(out);
+            hexdump(addr, &bytes, &mut out);
+            outputln!(out);
+        }
+        None => outputln!(out, "Failed to read {:#x} bytes of physical memory at {:#x}", len, addr),
+    }
+}
+
+pub(crate) fn virt_to_phys(guest: &mut dyn Guest, addr: target_ptr_t, mut out: impl std::fmt::Write) {
+    match guest.virt_to_phys(addr) {
+        Some(phys) => outputln!(out, "{:#x} -> {:#x}", addr, phys),
+        None => outputln!(out, "{:#x} is not mapped", addr),
+    }
+}
+
+pub(crate) fn page_walk(guest: &mut dyn Guest, addr: target_ptr_t, mut out: impl std::fmt::Write) {
+    let walk = match guest.page_walk(addr) {
+        Some(walk) => walk,
+        None => {
+            outputln!(out, "Page walks are only supported on x86, try v2p instead");
+            return
+        }
+    };
+
+    outputln!(out);
+    if walk.entries.is_empty() {
+        outputln!(out, "Paging is disabled");
+    }
+
+    for entry in &walk.entries {
+        outputln!(
+            out,
+            "{:<6} @ {:#012x}: {:#018x} [ {} ]",
+            entry.level,
+            entry.addr,
+            entry.value,
+            entry.flags().join(" ")
+        );
+    }
+
+    match (walk.phys, walk.permissions()) {
+        (Some(phys), Some(perms)) => outputln!(out, "{:#x} -> {:#x} ({})", addr, phys, perms),
+        (Some(phys), None) => outputln!(out, "{:#x} -> {:#x}", addr, phys),
+        (None, _) => outputln!(out, "{:#x} is not mapped", addr),
+    }
+    outputln!(out);
+}
+
+fn hexdump(addr: target_ptr_t, bytes: &[u8], mut out: impl std::fmt::Write) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = line.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+
+        outputln!(out, "{:#012x}: {:<47}  {}", addr as u64 + (i * 16) as u64, hex.join(" "), ascii);
+    }
+}
@@ -1,18 +1,143 @@
-use panda::prelude::*;
-use panda::plugins::osi::OSI;
-
 use gdbstub::outputln;
+use serde::Serialize;
+
+use crate::backend::{Guest, Process};
+
+/// Everything `procinfo` shows about a process
+struct ProcDetails {
+    proc: Process,
+    executable: Option<String>,
+    command_line: Option<Vec<String>>,
+    mapping_count: usize,
+    /// Only known for the current process
+    in_shared_object: Option<bool>,
+    running_threads: Vec<u64>,
+    open_files: Vec<(u32, String)>,
+}
+
+/// A process as printed by `procinfo --json`
+#[derive(Serialize)]
+struct ProcInfoJson {
+    name: String,
+    pid: u64,
+    asid: String,
+    ppid: u64,
+    create_time: u64,
+    executable: Option<String>,
+    command_line: Option<Vec<String>>,
+    mappings: usize,
+    in_shared_object: Option<bool>,
+    running_threads: Vec<u64>,
+    open_files: Vec<OpenFileJson>,
+}
+
+#[derive(Serialize)]
+struct OpenFileJson {
+    fd: u32,
+    path: String,
+}
+
+/// Gather details of the current process or the one with the given PID
+fn gather(guest: &mut dyn Guest, pid: Option<u64>) -> Option<ProcDetails> {
+    let current = guest.current_process();
+    let proc = match pid {
+        Some(pid) => guest.processes().into_iter().find(|proc| proc.pid as u64 == pid)?,
+        None => current.clone(),
+    };
+
+    let mappings = guest.process_mappings(proc.pid).unwrap_or_default();
+    // The binary is mapped first, ahead of the loader and libraries
+    let executable = mappings.iter()
+        .find(|mapping| mapping.is_file_backed())
+        .and_then(|mapping| mapping.file.clone());
 
-pub(crate) fn print(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
-    let proc = OSI.get_current_process(cpu);
+    let in_shared_object = if proc.pid == current.pid {
+        Some(guest.in_shared_object())
+    } else {
+        None
+    };
+
+    let running_threads = guest.running_threads()
+        .into_iter()
+        .filter(|thread| thread.pid == proc.pid)
+        .map(|thread| thread.tid as u64)
+        .collect();
+
+    Some(ProcDetails {
+        executable,
+        command_line: guest.command_line(proc.pid),
+        mapping_count: mappings.len(),
+        in_shared_object,
+        running_threads,
+        open_files: guest.open_files(proc.pid),
+        proc,
+    })
+}
+
+pub(crate) fn print(guest: &mut dyn Guest, pid: Option<u64>, mut out: impl std::fmt::Write) {
+    let details = match gather(guest, pid) {
+        Some(details) => details,
+        None => {
+            outputln!(out, "No such process, see proclist");
+            return
+        }
+    };
+    let proc = &details.proc;
 
     outputln!(out);
-    outputln!(out, "{}", proc.get_name());
+    outputln!(out, "{}", proc.name);
     outputln!(out, "====================");
     outputln!(out, "PID: {}", proc.pid);
     outputln!(out, "ASID: {:#x?}", proc.asid);
     outputln!(out, "Parent PID: {}", proc.ppid);
     outputln!(out, "Creation time: {}", proc.create_time);
-    outputln!(out, "PC in shared library: {}", OSI.in_shared_object(cpu, &*proc));
+    outputln!(out, "Executable: {}", details.executable.as_deref().unwrap_or("unknown"));
+    match &details.command_line {
+        Some(args) => outputln!(out, "Command line: {}", args.join(" ")),
+        None => outputln!(out, "Command line: unknown (only seen for processes stopped on entry)"),
+    }
+    outputln!(out, "Mappings: {}", details.mapping_count);
+    if let Some(in_shared_object) = details.in_shared_object {
+        outputln!(out, "PC in shared library: {}", in_shared_object);
+    }
+
+    if details.running_threads.is_empty() {
+        outputln!(out, "Threads on a vCPU: none");
+    } else {
+        let tids: Vec<String> = details.running_threads.iter().map(u64::to_string).collect();
+        outputln!(out, "Threads on a vCPU: {}", tids.join(", "));
+    }
+
+    outputln!(out, "Open files:");
+    for (fd, path) in &details.open_files {
+        outputln!(out, "  {:>4} -> {}", fd, path);
+    }
     outputln!(out);
 }
+
+pub(crate) fn print_json(guest: &mut dyn Guest, pid: Option<u64>, mut out: impl std::fmt::Write) {
+    let details = match gather(guest, pid) {
+        Some(details) => details,
+        None => {
+            outputln!(out, "No such process, see proclist");
+            return
+        }
+    };
+
+    super::print_json(&ProcInfoJson {
+        name: details.proc.name,
+        pid: details.proc.pid as u64,
+        asid: format!("{:#x}", details.proc.asid),
+        ppid: details.proc.ppid as u64,
+        create_time: details.proc.create_time,
+        executable: details.executable,
+        command_line: details.command_line,
+        mappings: details.mapping_count,
+        in_shared_object: details.in_shared_object,
+        running_threads: details.running_threads,
+        open_files: details.open_files
+            .into_iter()
+            .map(|(fd, path)| OpenFileJson { fd, path })
+            .collect(),
+    }, out);
+}
@@ -1,14 +1,26 @@
 use panda::prelude::*;
-use panda::plugins::osi::OSI;
 
 use gdbstub::outputln;
+use serde::Serialize;
 use tabwriter::{TabWriter, Alignment};
 
+use std::collections::HashSet;
 use std::io::Write;
 
-pub(crate) fn print(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
-    let procs = OSI.get_processes(cpu);
-    let current_pid = OSI.get_current_process(cpu).pid;
+use super::parser::ProcFilter;
+use crate::backend::{Guest, Process};
+
+fn matches(proc: &Process, filter: &Option<ProcFilter>) -> bool {
+    match filter {
+        None => true,
+        Some(ProcFilter::Pid(pid)) => proc.pid as u64 == *pid,
+        Some(ProcFilter::Name(name)) => proc.name.contains(name.as_str()),
+    }
+}
+
+pub(crate) fn print(guest: &mut dyn Guest, filter: Option<ProcFilter>, mut out: impl std::fmt::Write) {
+    let procs = guest.processes();
+    let current_pid = guest.current_process().pid;
 
     outputln!(out);
 
@@ -19,7 +31,7 @@ pub(crate) fn print(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
     let _ = writeln!(output, " \t===\t====\t======\t===========\t============");
 
     #[allow(unused_must_use)]
-    for proc in procs.iter() {
+    for proc in procs.iter().filter(|proc| matches(proc, &filter)) {
         writeln!(
             output,
             "{}\t{}\t{:#x?}\t{}\t{}\t{}",
@@ -28,7 +40,7 @@ pub(crate) fn print(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
             proc.asid,
             proc.ppid,
             proc.create_time,
-            proc.get_name()
+            proc.name
         );
     }
 
@@ -40,3 +52,121 @@ pub(crate) fn print(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
     outputln!(out, "{}", output);
     outputln!(out);
 }
+
+/// Print processes as a tree of parents and children. With a filter, each matching
+/// process is shown along with its descendants.
+pub(crate) fn print_tree(guest: &mut dyn Guest, filter: Option<ProcFilter>, mut out: impl std::fmt::Write) {
+    let procs = guest.processes();
+    let current_pid = guest.current_process().pid;
+
+    let is_root = |proc: &Process| {
+        if filter.is_some() {
+            // Matches under another match are already shown in its subtree
+            matches(proc, &filter) && !ancestors(&procs, proc).any(|parent| matches(parent, &filter))
+        } else {
+            proc.ppid == proc.pid || !procs.iter().any(|parent| parent.pid == proc.ppid)
+        }
+    };
+
+    outputln!(out);
+    let mut shown = HashSet::new();
+    for root in procs.iter().filter(|&proc| is_root(proc)) {
+        print_subtree(&procs, root, current_pid, "", "", &mut shown, &mut out);
+    }
+    outputln!(out);
+}
+
+fn print_subtree(
+    procs: &[Process],
+    proc: &Process,
+    current_pid: target_ulong,
+    prefix: &str,
+    child_prefix: &str,
+    shown: &mut HashSet<target_ulong>,
+    out: &mut impl std::fmt::Write,
+) {
+    // Guard against cycles, such as the idle task being its own parent
+    if !shown.insert(proc.pid) {
+        return
+    }
+
+    outputln!(
+        out,
+        "{} {}{} {} (ASID {:#x})",
+        if proc.pid == current_pid { '*' } else { ' ' },
+        prefix,
+        proc.pid,
+        proc.name,
+        proc.asid
+    );
+
+    let children: Vec<&Process> = procs.iter()
+        .filter(|child| child.ppid == proc.pid && child.pid != proc.pid)
+        .collect();
+
+    for (i, child) in children.iter().enumerate() {
+        let (prefix, next_prefix) = if i + 1 == children.len() {
+            ("└─ ", "   ")
+        } else {
+            ("├─ ", "│  ")
+        };
+
+        print_subtree(
+            procs,
+            child,
+            current_pid,
+            &format!("{}{}", child_prefix, prefix),
+            &format!("{}{}", child_prefix, next_prefix),
+            shown,
+            out,
+        );
+    }
+}
+
+/// Walk up the parents of a process
+fn ancestors<'a>(procs: &'a [Process], proc: &Process) -> impl Iterator<Item = &'a Process> {
+    let mut seen = HashSet::new();
+    let mut ppid = proc.ppid;
+    seen.insert(proc.pid);
+
+    std::iter::from_fn(move || {
+        let parent = procs.iter().find(|parent| parent.pid == ppid)?;
+        if !seen.insert(parent.pid) {
+            return None;
+        }
+
+        ppid = parent.ppid;
+        Some(parent)
+    })
+}
+
+/// A process as listed by `proclist --json`
+#[derive(Serialize)]
+struct ProcessJson<'a> {
+    pid: u64,
+    asid: String,
+    ppid: u64,
+    create_time: u64,
+    name: &'a str,
+    /// Whether this is the process currently running
+    current: bool,
+}
+
+pub(crate) fn print_json(guest: &mut dyn Guest, filter: Option<ProcFilter>, out: impl std::fmt::Write) {
+    let procs = guest.processes();
+    let current_pid = guest.current_process().pid;
+
+    let procs: Vec<ProcessJson> = procs.iter()
+        .filter(|proc| matches(proc, &filter))
+        .map(|proc| ProcessJson {
+            pid: proc.pid as u64,
+            asid: format!("{:#x}", proc.asid),
+            ppid: proc.ppid as u64,
+            create_time: proc.create_time,
+            name: &proc.name,
+            current: proc.pid == current_pid,
+        })
+        .collect();
+
+    super::print_json(&procs, out);
+}
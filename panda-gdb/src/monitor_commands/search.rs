@@ -0,0 +1,130 @@
+use panda::prelude::*;
+
+use gdbstub::outputln;
+
+use super::parser::SearchPattern;
+use crate::backend::Guest;
+use crate::memory_map;
+
+const PAGE_SIZE: target_ptr_t = 0x1000;
+
+/// Stop searching after this many hits so a short pattern can't flood the console
+const MAX_HITS: usize = 256;
+
+/// Largest range searched with `in START END`. Commands run on the PANDA thread, so a
+/// huge range would hold up the replay for as long as it takes to walk it.
+const MAX_RANGE_SIZE: target_ptr_t = 0x1000_0000;
+
+impl SearchPattern {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            SearchPattern::Bytes(bytes) => bytes.clone(),
+            SearchPattern::Ascii(string) => string.as_bytes().to_vec(),
+            SearchPattern::Utf16(string) => string.encode_utf16()
+                .flat_map(|c| {
+                    #[cfg(any(feature = "mips", feature = "ppc"))] {
+                        c.to_be_bytes()
+                    }
+
+                    #[cfg(not(any(feature = "mips", feature = "ppc")))] {
+                        c.to_le_bytes()
+                    }
+                })
+                .collect(),
+            SearchPattern::Pointer(ptr) => memory_map::ptr_to_bytes(*ptr),
+        }
+    }
+}
+
+pub(crate) fn print(
+    guest: &mut dyn Guest,
+    pattern: SearchPattern,
+    range: Option<(target_ptr_t, target_ptr_t)>,
+    mut out: impl std::fmt::Write,
+) {
+    if let Some((start, end)) = range {
+        if end <= start {
+            outputln!(out, "End address must be after the start address");
+            return
+        }
+
+        if end - start > MAX_RANGE_SIZE {
+            outputln!(out, "Ranges are limited to {:#x} bytes, search a smaller range", MAX_RANGE_SIZE);
+            return
+        }
+    }
+
+    let pattern = pattern.to_bytes();
+    let mappings = guest.mappings();
+
+    let ranges = match range {
+        Some(range) => vec![range],
+        None => mappings.iter()
+            .map(|mapping| (mapping.base, mapping.end()))
+            .collect(),
+    };
+
+    let mut hits = Vec::new();
+    for (start, end) in ranges {
+        search_range(guest, start, end, &pattern, &mut hits);
+    }
+
+    outputln!(out);
+    for &hit in &hits {
+        match memory_map::symbolize(&mappings, hit) {
+            Some(location) => outputln!(out, "{:#x} ({})", hit, location),
+            None => outputln!(out, "{:#x}", hit),
+        }
+    }
+
+    if hits.len() >= MAX_HITS {
+        outputln!(out, "Stopped after {} hits", MAX_HITS);
+    } else {
+        outputln!(out, "{} hit(s)", hits.len());
+    }
+    outputln!(out);
+}
+
+/// Search `start..end` a page at a time, skipping any page that can't be read
+fn search_range(
+    guest: &mut dyn Guest,
+    start: target_ptr_t,
+    end: target_ptr_t,
+    pattern: &[u8],
+    hits: &mut Vec<target_ptr_t>,
+) {
+    if pattern.is_empty() {
+        return
+    }
+
+    // Bytes read so far that a match starting in an earlier page could still use
+    let mut carry: Vec<u8> = Vec::new();
+    let mut page = start & !(PAGE_SIZE - 1);
+
+    while page < end && hits.len() < MAX_HITS {
+        let chunk_start = page.max(start);
+        let chunk_end = page.saturating_add(PAGE_SIZE).min(end);
+
+        match guest.read_memory(chunk_start, (chunk_end - chunk_start) as usize) {
+            Some(bytes) => {
+                let buf_start = chunk_start - carry.len() as target_ptr_t;
+                carry.extend_from_slice(&bytes);
+
+                for (i, window) in carry.windows(pattern.len()).enumerate() {
+                    if window == pattern && hits.len() < MAX_HITS {
+                        hits.push(buf_start + i as target_ptr_t);
+                    }
+                }
+
+                let keep = (pattern.len() - 1).min(carry.len());
+                carry.drain(..carry.len() - keep);
+            }
+            None => carry.clear(),
+        }
+
+        page = match page.checked_add(PAGE_SIZE) {
+            Some(page) => page,
+            None => break,
+        };
+    }
+}
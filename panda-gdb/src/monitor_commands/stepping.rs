@@ -0,0 +1,27 @@
+use gdbstub::outputln;
+
+use crate::backend::Guest;
+
+pub(crate) fn finish_kernel(guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
+    if !guest.in_kernel() {
+        outputln!(out, "Already in user mode");
+        return
+    }
+
+    let asid = guest.asid();
+    guest.finish_kernel(asid);
+
+    outputln!(out, "Continue to run until ASID {:#x} returns to user mode", asid);
+}
+
+pub(crate) fn user_only(guest: &mut dyn Guest, enabled: Option<bool>, mut out: impl std::fmt::Write) {
+    if let Some(enabled) = enabled {
+        guest.set_user_only_stepping(enabled);
+    }
+
+    if guest.user_only_stepping() {
+        outputln!(out, "Steps from user mode skip over kernel code");
+    } else {
+        outputln!(out, "Steps from user mode stop in kernel code");
+    }
+}
@@ -1,14 +1,59 @@
-use panda::prelude::*;
-use panda::plugins::osi::OSI;
-
 use gdbstub::outputln;
+use serde::Serialize;
+
+use crate::backend::{Guest, Thread};
+
+/// A thread as printed by `threadinfo --json`
+#[derive(Serialize)]
+struct ThreadInfoJson {
+    pid: u64,
+    tid: u64,
+}
+
+/// The current thread, or the threads of a process which are running on a vCPU.
+/// OSI can only see threads while they run.
+fn gather(guest: &mut dyn Guest, pid: Option<u64>) -> Vec<Thread> {
+    match pid {
+        Some(pid) => guest.running_threads()
+            .into_iter()
+            .filter(|thread| thread.pid as u64 == pid)
+            .collect(),
+        None => vec![guest.current_thread()],
+    }
+}
 
-pub(crate) fn print(cpu: &mut CPUState, mut out: impl std::fmt::Write) {
-    let thread = OSI.get_current_thread(cpu);
+pub(crate) fn print(guest: &mut dyn Guest, pid: Option<u64>, mut out: impl std::fmt::Write) {
+    let threads = gather(guest, pid);
 
     outputln!(out);
-    outputln!(out, "Current thread");
-    outputln!(out, "pid: {}", thread.pid);
-    outputln!(out, "tid: {}", thread.tid);
+    match pid {
+        Some(pid) if threads.is_empty() => {
+            outputln!(out, "No thread of PID {} is running on a vCPU", pid);
+        }
+        Some(pid) => outputln!(out, "Threads of PID {} running on a vCPU", pid),
+        None => outputln!(out, "Current thread"),
+    }
+
+    for thread in threads {
+        outputln!(out, "pid: {}", thread.pid);
+        outputln!(out, "tid: {}", thread.tid);
+    }
     outputln!(out);
 }
+
+pub(crate) fn print_json(guest: &mut dyn Guest, pid: Option<u64>, out: impl std::fmt::Write) {
+    let threads: Vec<ThreadInfoJson> = gather(guest, pid)
+        .into_iter()
+        .map(|thread| ThreadInfoJson {
+            pid: thread.pid as u64,
+            tid: thread.tid as u64,
+        })
+        .collect();
+
+    // A single object for the current thread, as before
+    if pid.is_none() && threads.len() == 1 {
+        super::print_json(&threads[0], out);
+    } else {
+        super::print_json(&threads, out);
+    }
+}
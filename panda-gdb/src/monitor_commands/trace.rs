@@ -0,0 +1,102 @@
+use gdbstub::outputln;
+use serde::Serialize;
+
+use super::parser::TraceQuery;
+use crate::backend::Guest;
+use crate::breakpoints::TraceFrame;
+
+/// How many frames `tfind` lists before asking for a narrower query
+const MAX_LISTED_FRAMES: usize = 50;
+
+#[derive(Serialize)]
+struct SavedFrame<'a> {
+    frame: usize,
+    pc: String,
+    instr_count: u64,
+    hit: u64,
+    values: Vec<SavedValue<'a>>,
+}
+
+#[derive(Serialize)]
+struct SavedValue<'a> {
+    item: &'a str,
+    value: Option<&'a str>,
+}
+
+pub(crate) fn find(guest: &mut dyn Guest, query: Option<TraceQuery>, mut out: impl std::fmt::Write) {
+    let dropped = guest.dropped_trace_frames();
+
+    guest.with_trace_frames(&mut |frames| {
+        outputln!(out);
+        if frames.is_empty() {
+            outputln!(out, "No trace frames collected, add a tracepoint with trace");
+            return
+        }
+
+        let matches: Vec<(usize, &TraceFrame)> = frames.iter()
+            .enumerate()
+            .filter(|(i, frame)| match query {
+                None => true,
+                Some(TraceQuery::Frame(n)) => *i == n,
+                Some(TraceQuery::Pc(pc)) => frame.pc == pc,
+            })
+            .collect();
+
+        if matches.is_empty() {
+            outputln!(out, "No matching trace frames ({} collected)", frames.len());
+        }
+
+        for (i, frame) in matches.iter().take(MAX_LISTED_FRAMES) {
+            outputln!(out, "#{:<5} {}", i, frame);
+        }
+
+        if matches.len() > MAX_LISTED_FRAMES {
+            outputln!(
+                out,
+                "... {} more, narrow it down with tfind N or tfind pc ADDR, or save them with tsave",
+                matches.len() - MAX_LISTED_FRAMES
+            );
+        }
+
+        if dropped > 0 {
+            outputln!(
+                out,
+                "The trace buffer is full, {} later frames weren't kept (tsave then tclear to collect more)",
+                dropped
+            );
+        }
+    })
+}
+
+pub(crate) fn save(guest: &mut dyn Guest, path: &str, mut out: impl std::fmt::Write) {
+    let mut written = Ok(0);
+    guest.with_trace_frames(&mut |frames| {
+        let saved: Vec<SavedFrame> = frames.iter()
+            .enumerate()
+            .map(|(i, frame)| SavedFrame {
+                frame: i,
+                pc: format!("{:#x}", frame.pc),
+                instr_count: frame.instr_count,
+                hit: frame.hit,
+                values: frame.values.iter()
+                    .map(|(item, value)| SavedValue { item, value: value.as_deref() })
+                    .collect(),
+            })
+            .collect();
+
+        written = serde_json::to_string_pretty(&saved)
+            .map_err(|err| err.to_string())
+            .and_then(|json| std::fs::write(path, json).map_err(|err| err.to_string()))
+            .map(|_| frames.len());
+    });
+
+    match written {
+        Ok(count) => outputln!(out, "Wrote {} trace frames to {}", count, path),
+        Err(err) => outputln!(out, "Failed to write {}: {}", path, err),
+    }
+}
+
+pub(crate) fn clear(guest: &mut dyn Guest, mut out: impl std::fmt::Write) {
+    guest.clear_trace_frames();
+    outputln!(out, "Cleared all trace frames");
+}
@@ -0,0 +1,201 @@
+use panda::prelude::*;
+
+/// One entry read while walking the page tables
+pub(crate) struct PageTableEntry {
+    pub level: &'static str,
+    /// Physical address the entry was read from
+    pub addr: u64,
+    pub value: u64,
+    has_permissions: bool,
+}
+
+/// The result of translating a virtual address by hand
+pub(crate) struct PageWalk {
+    pub entries: Vec<PageTableEntry>,
+    /// Physical address the virtual address translates to, if every level was present
+    pub phys: Option<u64>,
+    nx_enabled: bool,
+}
+
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const PAGE_SIZE: u64 = 1 << 7;
+const NO_EXECUTE: u64 = 1 << 63;
+
+impl PageTableEntry {
+    /// Names of the flags set in the entry
+    pub(crate) fn flags(&self) -> Vec<&'static str> {
+        const FLAGS: &[(u64, &str)] = &[
+            (PRESENT, "P"),
+            (WRITABLE, "RW"),
+            (1 << 2, "US"),
+            (1 << 3, "PWT"),
+            (1 << 4, "PCD"),
+            (1 << 5, "A"),
+            (1 << 6, "D"),
+            (PAGE_SIZE, "PS"),
+            (1 << 8, "G"),
+            (NO_EXECUTE, "NX"),
+        ];
+
+        FLAGS.iter()
+            .filter(|&&(flag, _)| self.value & flag != 0)
+            .map(|&(_, name)| name)
+            .collect()
+    }
+}
+
+impl PageWalk {
+    /// Effective `rwx` permissions of the page, combining the flags of every level.
+    /// Returns `None` if the page isn't mapped.
+    pub(crate) fn permissions(&self) -> Option<String> {
+        self.phys?;
+
+        let all = |flag| self.entries.iter()
+            .filter(|entry| entry.has_permissions)
+            .all(|entry| entry.value & flag != 0);
+        let executable = !self.nx_enabled
+            || self.entries.iter().all(|entry| entry.value & NO_EXECUTE == 0);
+
+        Some(format!(
+            "r{}{}",
+            if all(WRITABLE) { 'w' } else { '-' },
+            if executable { 'x' } else { '-' },
+        ))
+    }
+}
+
+/// Walk the page tables of the current address space for `addr`
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+pub(crate) fn walk(cpu: &mut CPUState, addr: target_ptr_t) -> Option<PageWalk> {
+    let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUX86State) };
+    let cr3 = env.cr[3] as target_ptr_t;
+
+    walk_in(cpu, cr3, addr)
+}
+
+/// Walk the x86 page tables rooted at `asid` (a CR3 value) for `addr`
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+pub(crate) fn walk_in(cpu: &mut CPUState, asid: target_ptr_t, addr: target_ptr_t) -> Option<PageWalk> {
+    const CR0_PG: u64 = 1 << 31;
+    const CR4_PSE: u64 = 1 << 4;
+    const CR4_PAE: u64 = 1 << 5;
+    const EFER_LMA: u64 = 1 << 10;
+    const EFER_NXE: u64 = 1 << 11;
+    const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+    const ADDR_MASK_32: u64 = 0xffff_f000;
+
+    struct Level {
+        name: &'static str,
+        shift: u32,
+        index_mask: u64,
+        /// Whether a set PS bit maps a large page and ends the walk here
+        can_be_large: bool,
+        /// Legacy PAE PDPTEs don't carry R/W or U/S bits
+        has_permissions: bool,
+    }
+
+    const fn level(name: &'static str, shift: u32, index_mask: u64, can_be_large: bool) -> Level {
+        Level { name, shift, index_mask, can_be_large, has_permissions: true }
+    }
+
+    let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUX86State) };
+    let (cr0, cr3, cr4) = (env.cr[0] as u64, asid as u64, env.cr[4] as u64);
+    let efer = env.efer as u64;
+    let addr = addr as u64;
+
+    if cr0 & CR0_PG == 0 {
+        return Some(PageWalk { entries: Vec::new(), phys: Some(addr), nx_enabled: false });
+    }
+
+    let (table, levels, entry_size) = if efer & EFER_LMA != 0 {
+        let levels = vec![
+            level("PML4E", 39, 0x1ff, false),
+            level("PDPTE", 30, 0x1ff, true),
+            level("PDE", 21, 0x1ff, true),
+            level("PTE", 12, 0x1ff, false),
+        ];
+        (cr3 & ADDR_MASK, levels, 8)
+    } else if cr4 & CR4_PAE != 0 {
+        let levels = vec![
+            Level { has_permissions: false, ..level("PDPTE", 30, 0b11, false) },
+            level("PDE", 21, 0x1ff, true),
+            level("PTE", 12, 0x1ff, false),
+        ];
+        (cr3 & 0xffff_ffe0, levels, 8)
+    } else {
+        let levels = vec![
+            level("PDE", 22, 0x3ff, cr4 & CR4_PSE != 0),
+            level("PTE", 12, 0x3ff, false),
+        ];
+        (cr3 & ADDR_MASK_32, levels, 4)
+    };
+    let frame_mask = if entry_size == 8 { ADDR_MASK } else { ADDR_MASK_32 };
+
+    let mut walk = PageWalk { entries: Vec::new(), phys: None, nx_enabled: efer & EFER_NXE != 0 };
+    let mut table = table;
+    for (i, level) in levels.iter().enumerate() {
+        let entry_addr = table + ((addr >> level.shift) & level.index_mask) * entry_size;
+        let bytes = panda::mem::physical_memory_read(entry_addr as _, entry_size as usize).ok()?;
+        let value = bytes.iter()
+            .rev()
+            .fold(0u64, |value, &byte| (value << 8) | byte as u64);
+
+        walk.entries.push(PageTableEntry {
+            level: level.name,
+            addr: entry_addr,
+            value,
+            has_permissions: level.has_permissions,
+        });
+
+        if value & PRESENT == 0 {
+            return Some(walk);
+        }
+
+        let is_last = i == levels.len() - 1;
+        if is_last || (level.can_be_large && value & PAGE_SIZE != 0) {
+            let offset_mask = (1u64 << level.shift) - 1;
+            walk.phys = Some((value & frame_mask & !offset_mask) | (addr & offset_mask));
+            return Some(walk);
+        }
+
+        table = value & frame_mask;
+    }
+
+    Some(walk)
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "i386")))]
+pub(crate) fn walk(_cpu: &mut CPUState, _addr: target_ptr_t) -> Option<PageWalk> {
+    None
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "i386")))]
+pub(crate) fn walk_in(_cpu: &mut CPUState, _asid: target_ptr_t, _addr: target_ptr_t) -> Option<PageWalk> {
+    None
+}
+
+/// Read virtual memory through the page tables of another address space. Fails if
+/// any page in the range isn't mapped.
+pub(crate) fn read_in(
+    cpu: &mut CPUState,
+    asid: target_ptr_t,
+    addr: target_ptr_t,
+    len: usize,
+) -> Option<Vec<u8>> {
+    const PAGE: target_ptr_t = 0x1000;
+
+    let end = addr.checked_add(len as target_ptr_t)?;
+    let mut data = Vec::with_capacity(len);
+    let mut cur = addr;
+    while cur < end {
+        let chunk_end = (cur & !(PAGE - 1)).saturating_add(PAGE).min(end);
+        let phys = walk_in(cpu, asid, cur)?.phys?;
+        let bytes = panda::mem::physical_memory_read(phys as _, (chunk_end - cur) as usize).ok()?;
+
+        data.extend_from_slice(&bytes);
+        cur = chunk_end;
+    }
+
+    Some(data)
+}
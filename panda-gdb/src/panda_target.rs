@@ -1,56 +1,58 @@
-use crate::{monitor_commands, target_state::{STATE, BreakStatus}};
+use crate::{backend::{Backend, Panda, ResumeActions}, exceptions, target_state::BreakStatus};
 use gdbstub::{
     target::{Target, TargetResult, TargetError, ext},
-    target::ext::base::singlethread::{
-        SingleThreadOps,
-        StopReason,
+    target::ext::base::multithread::{
+        MultiThreadOps,
+        ThreadStopReason,
         ResumeAction,
     },
     arch::Arch,
+    common::Tid,
     outputln,
 };
 
-use panda::plugins::osi::OSI;
+use std::fmt::Write;
 
-use std::convert::TryInto;
+/// GDB's view of the guest. The emulator itself is reached through `backend`, which
+/// is PANDA except in tests.
+pub struct PandaTarget<B = Panda> {
+    backend: B,
+    /// How GDB asked for the guest to be resumed, collected before `resume`
+    actions: ResumeActions,
+}
 
-pub struct PandaTarget;
+impl<B: Backend> PandaTarget<B> {
+    pub(crate) fn new(backend: B) -> Self {
+        Self {
+            backend,
+            actions: ResumeActions::default(),
+        }
+    }
+}
 
 #[cfg(feature = "x86_64")]
-use gdbstub_arch::x86::{X86_64_SSE as X86_64, reg::{X86_64CoreRegs, X86SegmentRegs, F80}};
+pub(crate) type GuestArch = gdbstub_arch::x86::X86_64_SSE;
 
 #[cfg(feature = "i386")]
-use gdbstub_arch::x86::{X86_SSE as X86, reg::{X86CoreRegs, F80}};
+pub(crate) type GuestArch = gdbstub_arch::x86::X86_SSE;
 
 #[cfg(feature = "arm")]
-use gdbstub_arch::arm::{Armv4t, reg::ArmCoreRegs};
+pub(crate) type GuestArch = gdbstub_arch::arm::Armv4t;
 
 #[cfg(feature = "ppc")]
-use gdbstub_arch::ppc::{PowerPc, reg::PowerPcCoreRegs};
+pub(crate) type GuestArch = gdbstub_arch::ppc::PowerPc;
 
 #[cfg(any(feature = "mips", feature = "mipsel"))]
-use gdbstub_arch::mips::{Mips, reg::MipsCoreRegs};
-
-impl Target for PandaTarget {
-    #[cfg(feature = "x86_64")]
-    type Arch = X86_64;
-    
-    #[cfg(feature = "i386")]
-    type Arch = X86;
-    
-    #[cfg(feature = "arm")]
-    type Arch = Armv4t;
-    
-    #[cfg(feature = "ppc")]
-    type Arch = PowerPc;
-
-    #[cfg(any(feature = "mips", feature = "mipsel"))]
-    type Arch = Mips;
+pub(crate) type GuestArch = gdbstub_arch::mips::Mips;
+
+pub(crate) type Registers = <GuestArch as Arch>::Registers;
 
+impl<B: Backend> Target for PandaTarget<B> {
+    type Arch = GuestArch;
     type Error = ();
 
     fn base_ops(&mut self) -> ext::base::BaseOps<Self::Arch, Self::Error> {
-        ext::base::BaseOps::SingleThread(
+        ext::base::BaseOps::MultiThread(
             self as _
         )
     }
@@ -68,203 +70,103 @@ impl Target for PandaTarget {
     }
 }
 
-// Implement the standard operations as a fake-single-thread target
-impl SingleThreadOps for PandaTarget {
+// Implement the standard operations with each vCPU as a thread. Only one vCPU runs
+// at a time under TCG, so all of them are paused whenever any of them stops.
+impl<B: Backend> MultiThreadOps for PandaTarget<B> {
     fn resume(
         &mut self,
-        action: ResumeAction,
+        default_resume_action: ResumeAction,
         _check_gdb_interrupt: ext::base::GdbInterrupt<'_>,
-    ) -> Result<StopReason<<Self::Arch as Arch>::Usize>, Self::Error> {
-        match action {
-            ResumeAction::Step => {
-                STATE.start_single_stepping();
-                STATE.cont.signal(());
-                Ok(
-                    match STATE.brk.wait_for() {
-                        BreakStatus::Break => StopReason::DoneStep,
-                        BreakStatus::Exit => StopReason::Exited(0),
-                    }
-                )
-            }
-            ResumeAction::Continue => {
-                STATE.cont.signal(());
-                Ok(
-                    match STATE.brk.wait_for() {
-                        BreakStatus::Break => StopReason::SwBreak,
-                        BreakStatus::Exit => StopReason::Exited(0),
-                    }
-                )
+    ) -> Result<ThreadStopReason<<Self::Arch as Arch>::Usize>, Self::Error> {
+        // A replay can't deliver a signal to the guest, so resuming with one (after an
+        // exception was reported as a signal, for example) just resumes
+        match default_resume_action {
+            ResumeAction::Step | ResumeAction::StepWithSignal(_) => self.actions.step = true,
+            ResumeAction::Continue | ResumeAction::ContinueWithSignal(_) => {
+                if self.actions.step_thread.is_some() {
+                    self.actions.step = true;
+                }
             }
-            _ => panic!("signals not supported")
         }
+
+        Ok(
+            match self.backend.resume(self.actions) {
+                BreakStatus::Breakpoint(_) => ThreadStopReason::SwBreak(self.backend.stopped_thread()),
+                BreakStatus::StepDone => ThreadStopReason::DoneStep,
+                BreakStatus::ContextSwitch | BreakStatus::KernelReturn => {
+                    ThreadStopReason::Signal(exceptions::SIGTRAP)
+                }
+                BreakStatus::Watchpoint(kind, addr) => ThreadStopReason::Watch {
+                    tid: self.backend.stopped_thread(),
+                    kind,
+                    addr,
+                },
+                BreakStatus::Exception(exception)
+                | BreakStatus::Syscall(exception)
+                | BreakStatus::Interrupt(exception) => {
+                    ThreadStopReason::Signal(exceptions::signal(exception))
+                }
+                BreakStatus::Exit(code) => ThreadStopReason::Exited(code),
+            }
+        )
     }
 
-    fn read_registers(
-        &mut self,
-        regs: &mut <Self::Arch as Arch>::Registers,
-    ) -> TargetResult<(), Self> {
+    fn clear_resume_actions(&mut self) -> Result<(), Self::Error> {
+        self.actions = ResumeActions::default();
 
-        let cpu = STATE.wait_for_cpu();
-
-        #[cfg(feature = "x86_64")] {
-            let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUX86State) };
-
-            let segments: [u32; 6] = (&env.segs.iter().map(|seg| seg.base as u32)
-                .collect::<Vec<_>>()[..6])
-                .try_into()
-                .unwrap();
-
-            let segments = X86SegmentRegs {
-                cs: segments[0],
-                ss: segments[1],
-                ds: segments[2],
-                es: segments[3],
-                fs: segments[4],
-                gs: segments[5],
-            };
-
-            *regs = X86_64CoreRegs {
-                eflags: env.eflags as _,
-                regs: (*env).regs.clone(),
-                rip: STATE.get_pc(),
-                segments,
-                st: (&env.fpregs.iter().map(fpreg_to_bytes).collect::<Vec<_>>()[..8]).try_into().unwrap(),
-                xmm: (&env.xmm_regs.iter().map(zmm_to_xmm).collect::<Vec<_>>()[..16]).try_into().unwrap(),
-                mxcsr: env.mxcsr,
-                ..Default::default()
-            };
-        }
-        
-        #[cfg(feature = "i386")] {
-            let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUX86State) };
-
-            use panda::sys::{R_EAX, R_EBX, R_ECX, R_EDX, R_ESP, R_EBP, R_ESI, R_EDI};
-
-            let segments: [u32; 6] = (&env.segs.iter().map(|seg| seg.base as u32)
-                .collect::<Vec<_>>()[..6])
-                .try_into()
-                .unwrap();
-
-            let segments = X86SegmentRegs {
-                cs: segments[0],
-                ss: segments[1],
-                ds: segments[2],
-                es: segments[3],
-                fs: segments[4],
-                gs: segments[5],
-            };
-
-            *regs = X86CoreRegs {
-                eflags: env.eflags,
-                eax: env.regs[R_EAX as usize],
-                ebx: env.regs[R_EBX as usize],
-                ecx: env.regs[R_ECX as usize],
-                edx: env.regs[R_EDX as usize],
-                esp: env.regs[R_ESP as usize],
-                ebp: env.regs[R_EBP as usize],
-                esi: env.regs[R_ESI as usize],
-                edi: env.regs[R_EDI as usize],
-                eip: STATE.get_pc(),
-                segments,
-                st: (&(*env).fpregs.iter().map(fpreg_to_bytes).collect::<Vec<_>>()[..8]).try_into().unwrap(),
-                xmm: (&(*env).xmm_regs.iter().map(zmm_to_xmm).collect::<Vec<_>>()[..8]).try_into().unwrap(),
-                mxcsr: (*env).mxcsr,
-                ..Default::default()
-            };
-        }
-        
-        #[cfg(feature = "arm")] {
-            let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUARMState) };
-
-            *regs = ArmCoreRegs {
-                r: env.regs[0..13].try_into().unwrap(),
-                sp: env.regs[13],
-                lr: env.regs[14],
-                pc: STATE.get_pc(),
-                cpsr: env.uncached_cpsr,
-                ..Default::default()
-            };
+        Ok(())
+    }
+
+    fn set_resume_action(&mut self, tid: Tid, action: ResumeAction) -> Result<(), Self::Error> {
+        match action {
+            ResumeAction::Step | ResumeAction::StepWithSignal(_) => self.actions.step_thread = Some(tid),
+            ResumeAction::Continue | ResumeAction::ContinueWithSignal(_) => (),
         }
 
-        #[cfg(feature = "ppc")] {
+        Ok(())
+    }
 
-        }
-        
-        #[cfg(any(feature = "mips", feature = "mipsel"))] {
-            let env = unsafe { &*(cpu.env_ptr as *const panda::sys::CPUMIPSState) };
-
-            regs.r = env.active_tc.gpr;
-            regs.lo = env.active_tc.LO[0];
-            regs.hi = env.active_tc.HI[0];
-            regs.pc = STATE.get_pc();
-            regs.cp0.status = env.CP0_Status as _;
-            regs.cp0.badvaddr = env.CP0_BadVAddr as _;
-            regs.cp0.cause = env.CP0_Cause as _;
-            // TODO: fpu
+    fn support_range_step(&mut self) -> Option<ext::base::multithread::MultiThreadRangeSteppingOps<Self>> {
+        Some(self)
+    }
+
+    fn list_active_threads(
+        &mut self,
+        thread_is_active: &mut dyn FnMut(Tid),
+    ) -> Result<(), Self::Error> {
+        for tid in self.backend.threads() {
+            thread_is_active(tid);
         }
 
         Ok(())
     }
 
-    fn write_registers(
+    fn read_registers(
         &mut self,
-        regs: &<Self::Arch as Arch>::Registers,
+        regs: &mut <Self::Arch as Arch>::Registers,
+        tid: Tid,
     ) -> TargetResult<(), Self> {
-        let cpu = STATE.wait_for_cpu();
-
-        #[cfg(feature = "x86_64")] {
-            let env = cpu.env_ptr as *mut panda::sys::CPUX86State;
-
-            unsafe {
-                (*env).regs = regs.regs.clone();
-                (*env).eip = regs.rip;
-                (*env).mxcsr = regs.mxcsr;
-            }
-        }
-        #[cfg(feature = "arm")] {
-            let env = unsafe { &mut *(cpu.env_ptr as *mut panda::sys::CPUARMState) };
-            
-            for i in 0..13 {
-                env.regs[i] = regs.r[i];
-            }
-            env.regs[13] = regs.sp;
-            env.regs[14] = regs.lr;
-            env.regs[15] = regs.pc;
-            env.uncached_cpsr = regs.cpsr;
-        }
-        #[cfg(feature = "i386")] {
-            let env = unsafe { &mut *(cpu.env_ptr as *mut panda::sys::CPUX86State) };
-
-            use panda::sys::{R_EAX, R_EBX, R_ECX, R_EDX, R_ESP, R_EBP, R_ESI, R_EDI};
-
-            for &(i, val) in &[
-                (R_EAX, regs.eax),
-                (R_EBX, regs.ebx),
-                (R_ECX, regs.ecx),
-                (R_EDX, regs.edx),
-                (R_ESP, regs.esp),
-                (R_EBP, regs.ebp),
-                (R_ESI, regs.esi),
-                (R_EDI, regs.edi),
-            ] {
-                env.regs[i as usize] = val;
-            }
-
-            env.eflags = regs.eflags;
-            STATE.set_pc(regs.eip);
-        }
+        *regs = self.backend.read_registers(tid).ok_or(TargetError::NonFatal)?;
 
         Ok(())
     }
 
+    fn write_registers(
+        &mut self,
+        regs: &<Self::Arch as Arch>::Registers,
+        tid: Tid,
+    ) -> TargetResult<(), Self> {
+        self.backend.write_registers(tid, regs.clone())
+            .ok_or(TargetError::NonFatal)
+    }
+
     fn read_addrs(
         &mut self,
         addr: <Self::Arch as Arch>::Usize,
-        out: &mut [u8]
+        out: &mut [u8],
+        tid: Tid,
     ) -> TargetResult<(), Self> {
-        let cpu = STATE.wait_for_cpu();
-
-        if let Some(mem) = cpu.try_mem_read(addr, out.len()) {
+        if let Some(mem) = self.backend.read_memory(tid, addr, out.len()) {
             out.clone_from_slice(&mem);
             Ok(())
         } else {
@@ -276,30 +178,48 @@ impl SingleThreadOps for PandaTarget {
         &mut self,
         addr: <Self::Arch as Arch>::Usize,
         data: &[u8],
+        tid: Tid,
     ) -> TargetResult<(), Self> {
-         let cpu = STATE.wait_for_cpu();
+        self.backend.write_memory(tid, addr, data.to_vec())
+            .ok_or(TargetError::NonFatal)
+    }
+}
 
-         cpu.mem_write(addr, data);
+// Range stepping: keep executing on the PANDA thread while the PC stays in the range,
+// rather than stopping after every instruction for GDB to check it
+impl<B: Backend> ext::base::multithread::MultiThreadRangeStepping for PandaTarget<B> {
+    fn set_resume_action_range_step(
+        &mut self,
+        tid: Tid,
+        start: <Self::Arch as Arch>::Usize,
+        end: <Self::Arch as Arch>::Usize,
+    ) -> Result<(), Self::Error> {
+        self.actions.step_thread = Some(tid);
+        self.actions.step_range = Some((start, end));
 
-         Ok(())
+        Ok(())
     }
 }
 
-// Breakpoints: just say we implement software breakpoints
-impl ext::breakpoints::Breakpoints for PandaTarget {
+// Breakpoints: software breakpoints and watchpoints
+impl<B: Backend> ext::breakpoints::Breakpoints for PandaTarget<B> {
     fn sw_breakpoint(&mut self) -> Option<ext::breakpoints::SwBreakpointOps<'_, Self>> {
         Some(self as _)
     }
+
+    fn hw_watchpoint(&mut self) -> Option<ext::breakpoints::HwWatchpointOps<'_, Self>> {
+        Some(self as _)
+    }
 }
 
 // Software breakpoints
-impl ext::breakpoints::SwBreakpoint for PandaTarget {
+impl<B: Backend> ext::breakpoints::SwBreakpoint for PandaTarget<B> {
     fn add_sw_breakpoint(
         &mut self,
         addr: <Self::Arch as Arch>::Usize,
         _kind: <Self::Arch as Arch>::BreakpointKind
     ) -> TargetResult<bool, Self> {
-        Ok(STATE.add_breakpoint(addr))
+        Ok(self.backend.add_breakpoint(addr))
     }
 
     fn remove_sw_breakpoint(
@@ -307,19 +227,38 @@ impl ext::breakpoints::SwBreakpoint for PandaTarget {
         addr: <Self::Arch as Arch>::Usize,
         _kind: <Self::Arch as Arch>::BreakpointKind
     ) -> TargetResult<bool, Self> {
-        Ok(STATE.remove_breakpoint(addr))
+        Ok(self.backend.remove_breakpoint(addr))
     }
 }
 
-impl ext::monitor_cmd::MonitorCmd for PandaTarget {
+// Watchpoints, checked by PANDA's memory callbacks while any are set
+impl<B: Backend> ext::breakpoints::HwWatchpoint for PandaTarget<B> {
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: <Self::Arch as Arch>::Usize,
+        kind: ext::breakpoints::WatchKind,
+    ) -> TargetResult<bool, Self> {
+        Ok(self.backend.add_watchpoint(addr, kind))
+    }
+
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: <Self::Arch as Arch>::Usize,
+        kind: ext::breakpoints::WatchKind,
+    ) -> TargetResult<bool, Self> {
+        Ok(self.backend.remove_watchpoint(addr, kind))
+    }
+}
+
+impl<B: Backend> ext::monitor_cmd::MonitorCmd for PandaTarget<B> {
     fn handle_monitor_cmd(
         &mut self,
         cmd: &[u8],
         mut out: ext::monitor_cmd::ConsoleOutput<'_>
     ) -> Result<(), Self::Error> {
         if let Ok(cmd) = std::str::from_utf8(cmd) {
-            let cpu = STATE.wait_for_cpu();
-            monitor_commands::handle_command(cmd, cpu, out);
+            let output = self.backend.monitor_command(cmd);
+            let _ = out.write_str(&output);
         } else {
             outputln!(out, "Command must be valid UTF-8");
         }
@@ -328,36 +267,15 @@ impl ext::monitor_cmd::MonitorCmd for PandaTarget {
     }
 }
 
-impl ext::section_offsets::SectionOffsets for PandaTarget {
+impl<B: Backend> ext::section_offsets::SectionOffsets for PandaTarget<B> {
     fn get_section_offsets(&mut self) -> Result<ext::section_offsets::Offsets<<Self::Arch as Arch>::Usize>, Self::Error> {
-        let cpu = STATE.wait_for_cpu();
-        let mut process = OSI.get_current_process(cpu);
-        let mappings = OSI.get_mappings(cpu, &mut *process);
-        if mappings.len() >= 3 {
-            let text = &mappings[0];
-            let data = &mappings[1];
-            let bss = &mappings[2];
-            Ok(ext::section_offsets::Offsets::Sections {
-                text: text.base,
-                data: data.base,
-                bss: Some(bss.base),
-            })
-        } else {
-            Err(())
+        match self.backend.section_offsets() {
+            Some((text, data, bss)) => Ok(ext::section_offsets::Offsets::Sections {
+                text,
+                data,
+                bss,
+            }),
+            None => Err(()),
         }
     }
 }
-
-#[cfg(any(feature = "x86_64", feature = "i386"))]
-fn fpreg_to_bytes(x: &panda::sys::FPReg) -> F80 {
-    unsafe {
-        std::mem::transmute_copy(x)
-    }
-}
-
-#[cfg(any(feature = "x86_64", feature = "i386"))]
-fn zmm_to_xmm(x: &panda::sys::ZMMReg) -> u128 {
-    unsafe {
-        std::mem::transmute_copy(x)
-    }
-}
@@ -0,0 +1,74 @@
+//! Noticing when the debugged process exits, and with what status
+
+use panda::prelude::*;
+use panda::plugins::osi::OSI;
+use panda::regs::{self, Reg};
+
+use crate::target_state::{BreakStatus, STATE};
+
+/// `exit_group` syscall number, and the registers holding the syscall number and the
+/// exit status
+#[cfg(feature = "x86_64")]
+const EXIT_GROUP: (target_ulong, Reg, Reg) = (231, Reg::RAX, Reg::RDI);
+#[cfg(feature = "i386")]
+const EXIT_GROUP: (target_ulong, Reg, Reg) = (252, Reg::EAX, Reg::EBX);
+#[cfg(feature = "arm")]
+const EXIT_GROUP: (target_ulong, Reg, Reg) = (248, Reg::R7, Reg::R0);
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+const EXIT_GROUP: (target_ulong, Reg, Reg) = (4246, Reg::V0, Reg::A0);
+#[cfg(feature = "ppc")]
+const EXIT_GROUP: (target_ulong, Reg, Reg) = (234, Reg::R0, Reg::R3);
+
+/// Whether the instruction at `pc` makes a system call
+#[cfg(feature = "x86_64")]
+pub(crate) fn is_syscall(cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+    cpu.try_mem_read(pc, 2).map_or(false, |insn| insn == [0x0f, 0x05])
+}
+
+#[cfg(feature = "i386")]
+pub(crate) fn is_syscall(cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+    // int 0x80 or sysenter
+    cpu.try_mem_read(pc, 2).map_or(false, |insn| insn == [0xcd, 0x80] || insn == [0x0f, 0x34])
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "i386")))]
+pub(crate) fn is_syscall(cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+    use std::convert::TryInto;
+
+    let insn = match cpu.try_mem_read(pc, 4).and_then(|insn| insn[..].try_into().ok()) {
+        Some(insn) => insn,
+        None => return false,
+    };
+
+    #[cfg(any(feature = "mips", feature = "ppc"))]
+    let insn = u32::from_be_bytes(insn);
+    #[cfg(not(any(feature = "mips", feature = "ppc")))]
+    let insn = u32::from_le_bytes(insn);
+
+    // svc (ARM state only), syscall or sc
+    if cfg!(feature = "arm") {
+        (insn >> 24) & 0xf == 0xf
+    } else if cfg!(feature = "ppc") {
+        insn == 0x4400_0002
+    } else {
+        insn & 0xfc00_003f == 0x0000_000c
+    }
+}
+
+/// If the debugged process is about to call `exit_group`, how it will exit
+pub(crate) fn exit_group_status(cpu: &mut CPUState) -> Option<BreakStatus> {
+    let (number, number_reg, status_reg) = EXIT_GROUP;
+    if regs::get_reg(cpu, number_reg) != number || !is_debugged_process(cpu) {
+        return None;
+    }
+
+    // Only the low byte of the status makes it to the parent
+    Some(BreakStatus::Exit(regs::get_reg(cpu, status_reg) as u8))
+}
+
+pub(crate) fn is_debugged_process(cpu: &mut CPUState) -> bool {
+    match STATE.get_pid() {
+        Some(pid) => OSI.get_current_process(cpu).pid as target_ulong == pid,
+        None => false,
+    }
+}
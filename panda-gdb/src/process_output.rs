@@ -0,0 +1,74 @@
+//! Forwarding what the debugged process writes to its terminal to GDB's console
+
+use panda::prelude::*;
+use panda::plugins::osi::OSI;
+use panda::regs::{self, Reg};
+
+use std::fmt::Write;
+
+use crate::file_descriptors;
+use crate::process_exit;
+use crate::target_state::STATE;
+
+/// `write` syscall number, and the registers holding the syscall number, the file
+/// descriptor, the buffer and its length
+#[cfg(feature = "x86_64")]
+const WRITE: (target_ulong, Reg, Reg, Reg, Reg) = (1, Reg::RAX, Reg::RDI, Reg::RSI, Reg::RDX);
+#[cfg(feature = "i386")]
+const WRITE: (target_ulong, Reg, Reg, Reg, Reg) = (4, Reg::EAX, Reg::EBX, Reg::ECX, Reg::EDX);
+#[cfg(feature = "arm")]
+const WRITE: (target_ulong, Reg, Reg, Reg, Reg) = (4, Reg::R7, Reg::R0, Reg::R1, Reg::R2);
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+const WRITE: (target_ulong, Reg, Reg, Reg, Reg) = (4004, Reg::V0, Reg::A0, Reg::A1, Reg::A2);
+#[cfg(feature = "ppc")]
+const WRITE: (target_ulong, Reg, Reg, Reg, Reg) = (4, Reg::R0, Reg::R3, Reg::R4, Reg::R5);
+
+/// Most bytes of a single write sent to GDB, so a huge write can't stall the replay
+const MAX_OUTPUT: usize = 0x10000;
+
+/// Bytes of output per `O` packet, keeping packets well within GDB's buffer size
+const PACKET_OUTPUT: usize = 0x200;
+
+/// If the debugged process is about to write to stdout, stderr or a terminal, show
+/// what it writes in GDB. Called at a syscall instruction, so this is what the process
+/// asked to write even if the write then fails.
+pub(crate) fn forward_write(cpu: &mut CPUState) {
+    let (number, number_reg, fd_reg, buf_reg, count_reg) = WRITE;
+    if regs::get_reg(cpu, number_reg) != number || !process_exit::is_debugged_process(cpu) {
+        return;
+    }
+
+    let fd = regs::get_reg(cpu, fd_reg) as i32;
+    if fd != 1 && fd != 2 {
+        let mut proc = OSI.get_current_process(cpu);
+        let to_terminal = file_descriptors::path(cpu, &mut *proc, fd)
+            .map_or(false, |path| file_descriptors::is_terminal(&path));
+
+        if !to_terminal {
+            return;
+        }
+    }
+
+    let buf = regs::get_reg(cpu, buf_reg) as target_ptr_t;
+    let count = (regs::get_reg(cpu, count_reg) as usize).min(MAX_OUTPUT);
+    let output = match cpu.try_mem_read(buf, count) {
+        Some(output) => output,
+        None => return,
+    };
+
+    for chunk in output.chunks(PACKET_OUTPUT) {
+        STATE.write_console(console_packet(chunk).as_bytes());
+    }
+}
+
+/// Encode output as an `O` packet, which GDB prints while the target is running
+pub(crate) fn console_packet(output: &[u8]) -> String {
+    let mut data = String::from("O");
+    for byte in output {
+        let _ = write!(data, "{:02x}", byte);
+    }
+
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+
+    format!("${}#{:02x}", data, checksum)
+}
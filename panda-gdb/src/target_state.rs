@@ -1,37 +1,108 @@
 use panda::prelude::*;
 
-use std::sync::{RwLock, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}};
-use std::sync::mpsc::{channel, Sender, Receiver};
-use std::collections::HashSet;
+use std::sync::{Arc, Condvar, RwLock, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::net::TcpStream;
+
+use gdbstub::common::Tid;
+use gdbstub::target::ext::breakpoints::WatchKind;
+
+use crate::branch_history::BranchHistory;
+use crate::breakpoints::{Breakpoint, TraceFrame, MAX_TRACE_FRAMES};
 
 pub struct State {
     single_step: AtomicBool,
+    user_only_stepping: AtomicBool,
+    step_return_asid: AtomicUsize,
+    /// ASID to stop in once it's back in user mode, set by `finish_kernel`
+    finish_kernel_asid: AtomicUsize,
+    /// Range `next` steps through without stopping, as `[start, end)`
+    step_range_start: AtomicUsize,
+    step_range_end: AtomicUsize,
     exit_kernel: AtomicBool,
+    /// Whether monitor commands print JSON instead of tables
+    json_output: AtomicBool,
+    /// Set once the debugged process is gone and GDB has been told
+    finished: AtomicBool,
     breakpoints: RwLock<HashSet<target_ptr_t>>,
-    cpu: Mutex<Option<usize>>,
+    monitor_breakpoints: RwLock<HashMap<target_ptr_t, Breakpoint>>,
+    trace_frames: Mutex<Vec<TraceFrame>>,
+    /// Frames not kept because the trace buffer was full
+    dropped_trace_frames: AtomicUsize,
+    branch_history: Mutex<BranchHistory>,
+    watchpoints: RwLock<Vec<(target_ptr_t, WatchKind)>>,
+    /// Exceptions to stop on, optionally only while a given ASID is current
+    catchpoints: RwLock<HashMap<i32, Option<target_ulong>>>,
+    stopped_tid: AtomicUsize,
+    step_tid: AtomicUsize,
     pc: AtomicUsize,
     pid: AtomicUsize,
+    memory_view: AtomicUsize,
+    attach_asid: AtomicUsize,
+    switch_asid: AtomicUsize,
+    last_switch: Mutex<Option<String>>,
+    /// Arguments of processes seen starting, by PID
+    command_lines: RwLock<HashMap<target_ulong, Vec<String>>>,
+    /// A second handle on GDB's connection, for console output while the guest runs
+    console: Mutex<Option<TcpStream>>,
     pub brk: Signal<BreakStatus>,
-    pub cont: Signal<()>,
+    requests: Signal<Request>,
 }
 
+/// Why the guest stopped
 #[derive(Copy, Clone, Debug)]
 pub enum BreakStatus {
-    Break,
-    Exit
+    /// Reached a GDB or monitor breakpoint at this address
+    Breakpoint(target_ptr_t),
+    /// Finished a single step or range step
+    StepDone,
+    /// Accessed memory watched by GDB
+    Watchpoint(WatchKind, target_ptr_t),
+    /// Raised a caught CPU exception
+    Exception(i32),
+    /// Entered the kernel for a system call through a caught exception (`int 0x80`, `swi`...)
+    Syscall(i32),
+    /// Received a caught interrupt
+    Interrupt(i32),
+    ContextSwitch,
+    /// Returned to user mode after `finish_kernel`
+    KernelReturn,
+    /// The debugged process or the replay ended, with this exit code
+    Exit(u8),
 }
 
 impl State {
     fn new() -> Self {
         State {
             single_step: AtomicBool::new(false),
+            user_only_stepping: AtomicBool::new(true),
+            step_return_asid: AtomicUsize::new(0),
+            finish_kernel_asid: AtomicUsize::new(0),
+            step_range_start: AtomicUsize::new(0),
+            step_range_end: AtomicUsize::new(0),
             exit_kernel: AtomicBool::new(false),
+            json_output: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
             breakpoints: RwLock::new(HashSet::new()),
+            monitor_breakpoints: RwLock::new(HashMap::new()),
+            trace_frames: Mutex::new(Vec::new()),
+            dropped_trace_frames: AtomicUsize::new(0),
+            branch_history: Mutex::new(BranchHistory::new()),
+            watchpoints: RwLock::new(Vec::new()),
+            catchpoints: RwLock::new(HashMap::new()),
             brk: Signal::new(),
-            cont: Signal::new(),
-            cpu: Mutex::new(None),
+            requests: Signal::new(),
+            stopped_tid: AtomicUsize::new(1),
+            step_tid: AtomicUsize::new(0),
             pc: AtomicUsize::new(0),
             pid: AtomicUsize::new(0),
+            memory_view: AtomicUsize::new(0),
+            attach_asid: AtomicUsize::new(0),
+            switch_asid: AtomicUsize::new(0),
+            last_switch: Mutex::new(None),
+            command_lines: RwLock::new(HashMap::new()),
+            console: Mutex::new(None),
         }
     }
 
@@ -40,6 +111,22 @@ impl State {
             .read()
             .unwrap()
             .contains(&pc)
+            || self.monitor_breakpoints
+                .read()
+                .unwrap()
+                .contains_key(&pc)
+    }
+
+    /// Whether to stop at `pc` for a breakpoint, evaluating any condition on it
+    pub fn hit_breakpoint(&self, cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+        // Monitor breakpoints go first so hits are counted and logged even when GDB
+        // also has a breakpoint here
+        let monitor_hit = match self.monitor_breakpoints.read().unwrap().get(&pc) {
+            Some(breakpoint) => breakpoint.should_stop(cpu, pc),
+            None => false,
+        };
+
+        monitor_hit || self.breakpoints.read().unwrap().contains(&pc)
     }
 
     pub fn exiting_kernel(&self) -> bool {
@@ -54,6 +141,14 @@ impl State {
         //self.exiting_kernel() && (pc & MASK == VALUE)
     }
 
+    pub fn finished_debugging(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    pub fn finish_debugging(&self) {
+        self.finished.store(true, Ordering::SeqCst)
+    }
+
     pub fn set_exit_kernel(&self) {
         self.exit_kernel.store(true, Ordering::SeqCst)
     }
@@ -77,33 +172,212 @@ impl State {
             .store(false, Ordering::SeqCst)
     }
 
-    // TODO: figure out some way to ensure CPUState doesn't outlive the breakpoint
-    // RefCell maybe?
-    pub fn wait_for_cpu(&self) -> &'static mut CPUState {
+    /// Record which vCPU stopped and where, before handing it to the debugger
+    pub fn set_stopped(&self, cpu: &mut CPUState, pc: target_ptr_t) {
+        self.stopped_tid.store(thread_id(cpu).get(), Ordering::SeqCst);
+        self.set_pc(pc);
+    }
+
+    /// Run debugger requests on the PANDA thread until the debugger resumes the guest
+    pub fn serve_requests(&self, cpu: &mut CPUState) {
         loop {
-            {
-                if let Some(_) = *self.cpu.lock().unwrap() {
-                    break
-                }
+            match self.requests.wait_for() {
+                Request::Run(request) => request(cpu),
+                Request::Resume => break,
             }
-            std::thread::sleep(std::time::Duration::from_millis(30));
         }
+    }
+
+    /// Let the stopped guest run again
+    pub fn resume(&self) {
+        self.requests.signal(Request::Resume);
+    }
+
+    /// Run `f` on the PANDA thread with the stopped vCPU and wait for its result. If
+    /// the guest is running, this waits for it to stop first. The CPU is only borrowed
+    /// for the duration of `f`, so it can't be used once the guest resumes.
+    pub fn with_cpu<T, F>(&self, f: F) -> T
+        where T: Send + 'static,
+              F: FnOnce(&mut CPUState) -> T + Send + 'static,
+    {
+        let response = Arc::new(Signal::new());
+        let sender = Arc::clone(&response);
+        self.requests.signal(Request::Run(Box::new(move |cpu| sender.signal(f(cpu)))));
+
+        response.wait_for()
+    }
+
+    /// Like `with_cpu`, but with the vCPU backing a given GDB thread. Returns `None`
+    /// if there's no such vCPU.
+    pub fn with_thread<T, F>(&self, tid: Tid, f: F) -> Option<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut CPUState) -> T + Send + 'static,
+    {
+        self.with_cpu(move |stopped_cpu| {
+            if thread_id(stopped_cpu) == tid {
+                return Some(f(stopped_cpu));
+            }
+
+            // SAFETY: this runs on the PANDA thread while the guest is stopped, so no
+            // other vCPU is executing
+            guest_cpus()
+                .into_iter()
+                .find(|&cpu| thread_id(unsafe { &*cpu }) == tid)
+                .map(|cpu| f(unsafe { &mut *cpu }))
+        })
+    }
+
+    /// The GDB thread of the vCPU that last stopped
+    pub fn stopped_thread(&self) -> Tid {
+        Tid::new(self.stopped_tid.load(Ordering::SeqCst)).unwrap()
+    }
+
+    /// The thread GDB asked to single step, or `None` if any vCPU may stop after a step
+    pub fn step_thread(&self) -> Option<Tid> {
+        Tid::new(self.step_tid.load(Ordering::SeqCst))
+    }
+
+    pub fn set_step_thread(&self, tid: Option<Tid>) {
+        self.step_tid.store(tid.map(Tid::get).unwrap_or(0), Ordering::SeqCst);
+    }
+
+    /// Whether a single step is pending that this vCPU should stop for at `pc`. When
+    /// range stepping, that's only once the PC leaves the range, and when stepping out
+    /// of the kernel, only once it is back in user mode of the same process.
+    pub fn single_stepping_on(&self, cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+        self.single_stepping()
+            && self.step_thread().map(|tid| tid == thread_id(cpu)).unwrap_or(true)
+            && !self.in_step_range(pc)
+            && match self.step_return_asid() {
+                Some(asid) => !panda::in_kernel(cpu) && panda::current_asid(cpu) == asid,
+                None => true,
+            }
+    }
+
+    /// Whether a step from user mode has just entered the kernel on this vCPU. The
+    /// kernel then runs without per-instruction checks until the step returns.
+    pub fn step_entered_kernel(&self, cpu: &mut CPUState) -> bool {
+        self.single_stepping()
+            && self.step_return_asid().is_some()
+            && self.step_thread().map(|tid| tid == thread_id(cpu)).unwrap_or(true)
+            && panda::in_kernel(cpu)
+    }
+
+    /// Whether a step that skipped over kernel code is back in user mode of the
+    /// process it was taken in
+    pub fn step_returned_from_kernel(&self, cpu: &mut CPUState) -> bool {
+        !self.single_stepping()
+            && self.step_thread().map(|tid| tid == thread_id(cpu)).unwrap_or(true)
+            && match self.step_return_asid() {
+                Some(asid) => !panda::in_kernel(cpu) && panda::current_asid(cpu) == asid,
+                None => false,
+            }
+    }
+
+    /// Keep stepping without stopping while the PC is within `start..end`
+    pub fn set_step_range(&self, start: target_ptr_t, end: target_ptr_t) {
+        self.step_range_start.store(start as usize, Ordering::SeqCst);
+        self.step_range_end.store(end as usize, Ordering::SeqCst);
+    }
+
+    pub fn clear_step_range(&self) {
+        self.set_step_range(0, 0);
+    }
+
+    pub fn in_step_range(&self, pc: target_ptr_t) -> bool {
+        let start = self.step_range_start.load(Ordering::SeqCst) as target_ptr_t;
+        let end = self.step_range_end.load(Ordering::SeqCst) as target_ptr_t;
+
+        (start..end).contains(&pc)
+    }
+
+    /// Whether steps taken from user mode should skip over any kernel code they enter
+    pub fn user_only_stepping(&self) -> bool {
+        self.user_only_stepping.load(Ordering::SeqCst)
+    }
+
+    pub fn set_user_only_stepping(&self, enabled: bool) {
+        self.user_only_stepping.store(enabled, Ordering::SeqCst)
+    }
 
-        unsafe {
-            std::mem::transmute(self.cpu.lock().unwrap().unwrap())
+    pub fn set_command_line(&self, pid: target_ulong, args: Vec<String>) {
+        self.command_lines.write().unwrap().insert(pid, args);
+    }
+
+    pub fn command_line(&self, pid: target_ulong) -> Option<Vec<String>> {
+        self.command_lines.read().unwrap().get(&pid).cloned()
+    }
+
+    pub fn set_console(&self, connection: TcpStream) {
+        *self.console.lock().unwrap() = Some(connection);
+    }
+
+    /// Send packets straight to GDB. Only valid while the guest is running, when
+    /// gdbstub is waiting in `resume` and not writing to the connection itself. Unless
+    /// GDB turned acks off, it acks these like any other packet, and gdbstub reads and
+    /// ignores those acks before the next command.
+    pub fn write_console(&self, packet: &[u8]) {
+        let mut console = self.console.lock().unwrap();
+        if let Some(connection) = console.as_mut() {
+            if connection.write_all(packet).is_err() {
+                // GDB is gone, so stop trying
+                *console = None;
+            }
         }
     }
 
-    pub fn set_cpu(&self, cpu: &mut CPUState) {
-        let mut stored_cpu = self.cpu.lock().unwrap();
+    /// Whether monitor commands that support it print JSON without being asked
+    pub fn json_output(&self) -> bool {
+        self.json_output.load(Ordering::SeqCst)
+    }
 
-        *stored_cpu = Some(cpu as *mut _ as usize);
+    pub fn set_json_output(&self, enabled: bool) {
+        self.json_output.store(enabled, Ordering::SeqCst)
     }
 
-    pub fn unset_cpu(&self) {
-        *self.cpu.lock().unwrap() = None;
+    /// Get the ASID a pending step has to return to user mode in before stopping
+    pub fn step_return_asid(&self) -> Option<target_ulong> {
+        match self.step_return_asid.load(Ordering::SeqCst) {
+            0 => None,
+            x => Some(x as _)
+        }
     }
-    
+
+    pub fn set_step_return_asid(&self, asid: target_ulong) {
+        self.step_return_asid.store(asid as usize, Ordering::SeqCst);
+    }
+
+    pub fn clear_step_return_asid(&self) {
+        self.step_return_asid.store(0, Ordering::SeqCst);
+    }
+
+    /// Stop the next time `asid` runs in user mode
+    pub fn set_finish_kernel(&self, asid: target_ulong) {
+        self.finish_kernel_asid.store(asid as usize, Ordering::SeqCst);
+    }
+
+    pub fn clear_finish_kernel(&self) {
+        self.finish_kernel_asid.store(0, Ordering::SeqCst);
+    }
+
+    /// Whether a pending `finish_kernel` has reached user mode in its process
+    pub fn finished_kernel(&self, cpu: &mut CPUState) -> bool {
+        match self.finish_kernel_asid.load(Ordering::SeqCst) {
+            0 => false,
+            asid => !panda::in_kernel(cpu) && panda::current_asid(cpu) as usize == asid,
+        }
+    }
+
+    /// Get the PC of a vCPU. The stopped vCPU's PC comes from the instruction callback,
+    /// since the one in its CPU state is only updated at block boundaries.
+    pub fn pc_of(&self, cpu: &mut CPUState) -> target_ptr_t {
+        if thread_id(cpu) == self.stopped_thread() {
+            self.get_pc()
+        } else {
+            panda::current_pc(cpu)
+        }
+    }
+
     pub fn set_pc(&self, pc: target_ptr_t) {
         self.pc.store(pc as usize, Ordering::SeqCst);
     }
@@ -126,6 +400,146 @@ impl State {
             .remove(&pc)
     }
 
+    /// Get the ASID to stop on context switches into or out of, if enabled
+    pub fn switch_catch_asid(&self) -> Option<target_ulong> {
+        match self.switch_asid.load(Ordering::SeqCst) {
+            0 => None,
+            x => Some(x as _)
+        }
+    }
+
+    pub fn catch_switches(&self, asid: target_ulong) {
+        self.switch_asid.store(asid as usize, Ordering::SeqCst);
+    }
+
+    pub fn stop_catching_switches(&self) {
+        self.switch_asid.store(0, Ordering::SeqCst);
+    }
+
+    pub fn last_switch(&self) -> Option<String> {
+        self.last_switch.lock().unwrap().clone()
+    }
+
+    pub fn set_last_switch(&self, description: String) {
+        *self.last_switch.lock().unwrap() = Some(description);
+    }
+
+    pub fn add_monitor_breakpoint(&self, pc: target_ptr_t, breakpoint: Breakpoint) {
+        self.monitor_breakpoints
+            .write()
+            .unwrap()
+            .insert(pc, breakpoint);
+    }
+
+    pub fn remove_monitor_breakpoint(&self, pc: target_ptr_t) -> bool {
+        self.monitor_breakpoints
+            .write()
+            .unwrap()
+            .remove(&pc)
+            .is_some()
+    }
+
+    /// Run `f` on each monitor breakpoint in address order
+    pub fn for_each_monitor_breakpoint(&self, mut f: impl FnMut(target_ptr_t, &Breakpoint)) {
+        let breakpoints = self.monitor_breakpoints.read().unwrap();
+        let mut addrs: Vec<_> = breakpoints.keys().copied().collect();
+        addrs.sort();
+
+        for addr in addrs {
+            f(addr, &breakpoints[&addr]);
+        }
+    }
+
+    pub fn add_watchpoint(&self, addr: target_ptr_t, kind: WatchKind) {
+        self.watchpoints.write().unwrap().push((addr, kind));
+    }
+
+    /// Remove a watchpoint, returning whether it was set
+    pub fn remove_watchpoint(&self, addr: target_ptr_t, kind: WatchKind) -> bool {
+        let mut watchpoints = self.watchpoints.write().unwrap();
+        match watchpoints.iter().position(|&watchpoint| watchpoint == (addr, kind)) {
+            Some(index) => {
+                watchpoints.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The watchpoint covered by an access of `size` bytes at `addr`, if any
+    pub fn hit_watchpoint(
+        &self,
+        addr: target_ptr_t,
+        size: usize,
+        is_write: bool,
+    ) -> Option<(WatchKind, target_ptr_t)> {
+        hit_watchpoint(&self.watchpoints.read().unwrap(), addr, size, is_write)
+    }
+
+    /// Keep a trace frame, unless the buffer is full. Like GDB's own trace buffer, the
+    /// earliest frames are kept rather than the latest.
+    pub fn push_trace_frame(&self, frame: TraceFrame) {
+        let mut frames = self.trace_frames.lock().unwrap();
+        if frames.len() < MAX_TRACE_FRAMES {
+            frames.push(frame);
+        } else {
+            self.dropped_trace_frames.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Run `f` on the trace frames collected so far, in the order they were collected
+    pub fn with_trace_frames<T>(&self, f: impl FnOnce(&[TraceFrame]) -> T) -> T {
+        f(&self.trace_frames.lock().unwrap())
+    }
+
+    pub fn dropped_trace_frames(&self) -> usize {
+        self.dropped_trace_frames.load(Ordering::SeqCst)
+    }
+
+    pub fn clear_trace_frames(&self) {
+        self.trace_frames.lock().unwrap().clear();
+        self.dropped_trace_frames.store(0, Ordering::SeqCst);
+    }
+
+    pub fn with_branch_history<T>(&self, f: impl FnOnce(&mut BranchHistory) -> T) -> T {
+        f(&mut self.branch_history.lock().unwrap())
+    }
+
+    pub fn catches_exception(&self, exception: i32, asid: target_ulong) -> bool {
+        match self.catchpoints.read().unwrap().get(&exception) {
+            Some(Some(catch_asid)) => *catch_asid == asid,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    pub fn add_catchpoint(&self, exception: i32, asid: Option<target_ulong>) {
+        self.catchpoints
+            .write()
+            .unwrap()
+            .insert(exception, asid);
+    }
+
+    pub fn remove_catchpoint(&self, exception: i32) -> bool {
+        self.catchpoints
+            .write()
+            .unwrap()
+            .remove(&exception)
+            .is_some()
+    }
+
+    pub fn catchpoints(&self) -> Vec<(i32, Option<target_ulong>)> {
+        let mut catchpoints: Vec<_> = self.catchpoints
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&exception, &asid)| (exception, asid))
+            .collect();
+
+        catchpoints.sort();
+        catchpoints
+    }
+
     pub fn is_pid_set(&self) -> bool {
         self.pid.load(Ordering::SeqCst) != 0
     }
@@ -144,37 +558,151 @@ impl State {
             x => Some(x as _)
         }
     }
+
+    /// Get the ASID whose page tables GDB memory reads should go through, if it has
+    /// been switched away from the current process
+    pub fn get_memory_view(&self) -> Option<target_ulong> {
+        match self.memory_view.load(Ordering::SeqCst) {
+            0 => None,
+            x => Some(x as _)
+        }
+    }
+
+    pub fn set_memory_view(&self, asid: target_ulong) {
+        self.memory_view.store(asid as usize, Ordering::SeqCst);
+    }
+
+    pub fn reset_memory_view(&self) {
+        self.memory_view.store(0, Ordering::SeqCst);
+    }
+
+    /// Get the ASID of the process the debugger is waiting to attach to
+    pub fn attach_target(&self) -> Option<target_ulong> {
+        match self.attach_asid.load(Ordering::SeqCst) {
+            0 => None,
+            x => Some(x as _)
+        }
+    }
+
+    pub fn set_attach_target(&self, asid: target_ulong) {
+        self.attach_asid.store(asid as usize, Ordering::SeqCst);
+    }
+
+    pub fn clear_attach_target(&self) {
+        self.attach_asid.store(0, Ordering::SeqCst);
+    }
+}
+
+/// GDB thread ids start at 1, so each vCPU is exposed as `cpu_index + 1`
+pub fn thread_id(cpu: &CPUState) -> Tid {
+    Tid::new(cpu.cpu_index as usize + 1).unwrap()
 }
 
+/// Get the GDB thread ids of every vCPU
+pub fn threads() -> Vec<Tid> {
+    guest_cpus()
+        .into_iter()
+        .map(|cpu| thread_id(unsafe { &*cpu }))
+        .collect()
+}
+
+/// Walk QEMU's list of vCPUs
+pub fn guest_cpus() -> Vec<*mut CPUState> {
+    let mut cpus = Vec::new();
+
+    // SAFETY: the vCPU list is only modified during machine setup
+    unsafe {
+        let mut cpu = panda::sys::cpus.tqh_first;
+        while !cpu.is_null() {
+            cpus.push(cpu);
+            cpu = (*cpu).node.tqe_next;
+        }
+    }
+
+    cpus
+}
+
+/// Work for the PANDA thread while the guest is stopped
+enum Request {
+    /// A debugger operation on the stopped vCPU
+    Run(Box<dyn FnOnce(&mut CPUState) + Send>),
+    Resume,
+}
+
+/// A queue one thread waits on for values from another
 pub struct Signal<T> {
-    recv: Mutex<Receiver<T>>,
-    send: Mutex<Sender<T>>
+    queue: Mutex<VecDeque<T>>,
+    ready: Condvar,
 }
 
 impl<T: Sized> Signal<T> {
     fn new() -> Self {
-        let (send, recv) = channel();
-        let (send, recv) = (Mutex::new(send), Mutex::new(recv));
-        Self { send, recv }
+        Self {
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+        }
     }
 
     pub fn wait_for(&self) -> T {
-        self.recv
-            .lock()
-            .unwrap()
-            .recv()
-            .unwrap()
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(x) = queue.pop_front() {
+                return x;
+            }
+
+            queue = self.ready.wait(queue).unwrap();
+        }
     }
 
     pub fn signal(&self, x: T) {
-        self.send
+        self.queue
             .lock()
             .unwrap()
-            .send(x)
-            .unwrap()
+            .push_back(x);
+        self.ready.notify_one();
     }
 }
 
 lazy_static::lazy_static!{
     pub static ref STATE: State = State::new();
 }
+
+/// The first of `watchpoints`, as `(addr, kind)`, covered by an access of `size` bytes
+/// at `addr`
+pub(crate) fn hit_watchpoint(
+    watchpoints: &[(target_ptr_t, WatchKind)],
+    addr: target_ptr_t,
+    size: usize,
+    is_write: bool,
+) -> Option<(WatchKind, target_ptr_t)> {
+    let end = addr.saturating_add(size as target_ptr_t);
+    watchpoints
+        .iter()
+        .find(|&&(watched, kind)| {
+            let kind_matches = match kind {
+                WatchKind::Write => is_write,
+                WatchKind::Read => !is_write,
+                WatchKind::ReadWrite => true,
+            };
+
+            let watched_end = watched.saturating_add(watched_len(watched));
+
+            kind_matches && addr < watched_end && watched < end
+        })
+        .map(|&(watched, kind)| (kind, watched))
+}
+
+/// How many bytes a watchpoint at `addr` covers. gdbstub doesn't pass on the length GDB
+/// asked for, but GDB splits every watched region into naturally aligned pieces of at most
+/// a pointer's size, so this takes the largest such piece starting at `addr`. Any extra
+/// bytes it covers only cause spurious hits, which GDB ignores for `watch` when the value
+/// hasn't changed.
+fn watched_len(addr: target_ptr_t) -> target_ptr_t {
+    let max_len = std::mem::size_of::<target_ptr_t>() as target_ptr_t;
+
+    if addr == 0 {
+        max_len
+    } else {
+        max_len.min(1 << addr.trailing_zeros())
+    }
+}
@@ -0,0 +1,478 @@
+//! Drive the GDB stub over a real connection, against the fake backend
+
+use gdbstub::GdbStub;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::backend::Process;
+use crate::backend::fake::{Fake, INSTRUCTION_SIZE};
+use crate::branch_history::Branch;
+use crate::breakpoints::TraceFrame;
+use crate::panda_target::PandaTarget;
+
+/// A minimal GDB client
+struct Client {
+    stream: TcpStream,
+    /// Whether packets from the stub are acked, as GDB does until no-ack mode
+    acks: bool,
+}
+
+impl Client {
+    /// Start a stub debugging `fake` and connect to it
+    fn connect(fake: Fake) -> Self {
+        let mut client = Self::connect_with_acks(fake);
+
+        // Skip acks for the rest of the session
+        assert_eq!(client.request("QStartNoAckMode"), "OK");
+        client.acks = false;
+
+        client
+    }
+
+    /// Connect without turning acks off
+    fn connect_with_acks(mut fake: Fake) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let (connection, _) = listener.accept().unwrap();
+            fake.console = connection.try_clone().ok();
+            let mut target = PandaTarget::new(fake);
+            let _ = GdbStub::new(connection).run(&mut target);
+        });
+
+        Self {
+            stream: TcpStream::connect(addr).unwrap(),
+            acks: true,
+        }
+    }
+
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", packet, checksum).unwrap();
+    }
+
+    fn receive(&mut self) -> String {
+        let mut byte = [0u8];
+
+        // Skip acks until the start of a packet
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut packet = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            packet.push(byte[0]);
+        }
+
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+
+        if self.acks {
+            self.stream.write_all(b"+").unwrap();
+        }
+
+        String::from_utf8(packet).unwrap()
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.receive()
+    }
+
+    /// Run a monitor command and collect its console output
+    fn monitor(&mut self, cmd: &str) -> String {
+        self.send(&format!("qRcmd,{}", encode_hex(cmd.as_bytes())));
+
+        let mut output = String::new();
+        loop {
+            let packet = self.receive();
+            match packet.strip_prefix('O') {
+                Some(text) => output.push_str(&String::from_utf8(decode_hex(text)).unwrap()),
+                None => {
+                    assert_eq!(packet, "OK");
+                    return output;
+                }
+            }
+        }
+    }
+
+    fn rip(&mut self) -> u64 {
+        // rip follows the 16 general purpose registers
+        let regs = self.request("g");
+        let rip = decode_hex(&regs[256..272]);
+
+        u64::from_le_bytes(std::convert::TryInto::try_into(&rip[..]).unwrap())
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn initial_stop() {
+    let mut client = Client::connect(Fake::default());
+
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.rip(), 0x1000);
+}
+
+#[test]
+fn continue_to_breakpoint() {
+    let mut client = Client::connect(Fake::default());
+
+    assert_eq!(client.request("Z0,1008,1"), "OK");
+    let stop = client.request("c");
+    assert!(stop.starts_with("T05"), "{}", stop);
+    assert!(stop.contains("swbreak"), "{}", stop);
+    assert_eq!(client.rip(), 0x1008);
+
+    // Without the breakpoint the process runs to its exit
+    assert_eq!(client.request("z0,1008,1"), "OK");
+    assert_eq!(client.request("c"), "W00");
+}
+
+#[test]
+fn single_step() {
+    let mut client = Client::connect(Fake::default());
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.rip(), 0x1000 + INSTRUCTION_SIZE);
+}
+
+#[test]
+fn resume_with_signal() {
+    let mut client = Client::connect(Fake::default());
+
+    // GDB passes on the signal an exception was reported as, which a replay can't deliver
+    assert_eq!(client.request("vCont;S0b:1"), "S05");
+    assert_eq!(client.rip(), 0x1000 + INSTRUCTION_SIZE);
+
+    assert_eq!(client.request("Z0,1010,1"), "OK");
+    assert!(client.request("vCont;C0b").contains("swbreak"));
+    assert_eq!(client.rip(), 0x1010);
+}
+
+#[test]
+fn range_step() {
+    let mut client = Client::connect(Fake::default());
+
+    assert_eq!(client.request("vCont;r1000,100c:1"), "S05");
+    assert_eq!(client.rip(), 0x100c);
+}
+
+#[test]
+fn watchpoint_stop() {
+    let mut fake = Fake::default();
+    fake.accesses.insert(0x100c, (0x1104, 4, true));
+    let mut client = Client::connect(fake);
+
+    // A read watchpoint ignores the write, a write watchpoint stops on the instruction
+    assert_eq!(client.request("Z3,1104,4"), "OK");
+    assert_eq!(client.request("Z2,1100,8"), "OK");
+    let stop = client.request("c");
+    assert!(stop.ends_with(";watch:1100;"), "{}", stop);
+    assert_eq!(client.rip(), 0x100c);
+}
+
+#[test]
+fn memory_access() {
+    let mut client = Client::connect(Fake::default());
+
+    assert_eq!(client.request("M1100,4:deadbeef"), "OK");
+    assert_eq!(client.request("m1100,4"), "deadbeef");
+}
+
+#[test]
+fn monitor_commands() {
+    let mut client = Client::connect(Fake::default());
+
+    assert!(client.monitor("proclist").contains("fake_process"));
+    assert!(client.monitor("meminfo").contains("/bin/fake_process"));
+    assert!(client.monitor("v2p 0x1100").contains("0x1100 -> 0x1100"));
+    assert!(client.monitor("v2p 0x9000").contains("is not mapped"));
+}
+
+#[test]
+fn monitor_memory_commands() {
+    let mut fake = Fake::default();
+
+    // A frame at 0x1800 returning to 0x1108, just after a call instruction
+    fake.regs.regs[6] = 0x1800;
+    fake.regs.regs[7] = 0x1800;
+    fake.memory[0x800..0x808].copy_from_slice(&0x1810u64.to_le_bytes());
+    fake.memory[0x808..0x810].copy_from_slice(&0x1108u64.to_le_bytes());
+    fake.memory[0x103] = 0xe8;
+    fake.memory[0x200..0x205].copy_from_slice(b"hello");
+    let mut client = Client::connect(fake);
+
+    let output = client.monitor("backtrace");
+    assert!(output.contains("#0   0x1000 in fake_process+0x0"), "{}", output);
+    assert!(output.contains("#1   0x1108 in fake_process+0x108"), "{}", output);
+
+    let output = client.monitor("search str \"hello\"");
+    assert!(output.contains("0x1200 (fake_process+0x200)"), "{}", output);
+    assert!(output.contains("1 hit(s)"), "{}", output);
+}
+
+#[test]
+fn monitor_process_details() {
+    let mut fake = Fake::default();
+    fake.processes.push(Process {
+        pid: 1300,
+        ppid: 1234,
+        asid: 0x2000,
+        create_time: 1,
+        name: String::from("child"),
+    });
+    fake.command_lines.insert(1234, vec![String::from("fake_process"), String::from("-v")]);
+    let mut client = Client::connect(fake);
+
+    let output = client.monitor("procinfo");
+    assert!(output.contains("Command line: fake_process -v"), "{}", output);
+    assert!(output.contains("/dev/pts/0"), "{}", output);
+    assert!(client.monitor("procinfo 1300").contains("Command line: unknown"));
+    assert!(client.monitor("procinfo 999").contains("No such process"));
+    let help = client.monitor("help procinfo");
+    assert!(help.contains("only known for a process stopped in on entry"));
+    assert!(help.contains("descriptors 0-255 only"));
+
+    let output = client.monitor("proclist tree 1234");
+    assert!(output.contains("fake_process"), "{}", output);
+    assert!(output.contains("└─ 1300 child"), "{}", output);
+    assert!(!client.monitor("proclist child").contains("fake_process"));
+}
+
+#[test]
+fn monitor_modules() {
+    let mut fake = Fake::default();
+    fake.mappings = vec![
+        (0x1000, 0x1000, String::from("/bin/fake_process")),
+        (0x3000, 0x1000, String::from("/lib/libc.so.6")),
+        (0x5000, 0x1000, String::from("/lib/libc.so.6")),
+    ];
+    fake.kernel_modules.push((0xffff0000, 0x1000, String::from("e1000")));
+    let mut client = Client::connect(fake);
+
+    let modules: serde_json::Value = serde_json::from_str(&client.monitor("modules --json")).unwrap();
+    assert_eq!(modules["modules"][1]["path"], "/lib/libc.so.6");
+    assert_eq!(modules["modules"][1]["size"], "0x3000");
+    assert_eq!(modules["kernel_modules"][0]["name"], "e1000");
+    // The fake has no page tables to walk, like architectures other than x86
+    assert_eq!(modules["modules"][1]["permissions"], "???,???");
+
+    let table = client.monitor("modules");
+    assert!(table.lines().any(|line| line.contains("???,???") && line.ends_with("/lib/libc.so.6")));
+
+    assert!(client.monitor("addr 0x5010").contains("libc.so.6+0x2010"));
+    assert!(client.monitor("addr 0xffff0010").contains("e1000+0x10"));
+    assert!(client.monitor("addr 0x9000").contains("isn't in any mapping"));
+}
+
+#[test]
+fn monitor_json_output() {
+    let mut client = Client::connect(Fake::default());
+
+    let procs: serde_json::Value = serde_json::from_str(&client.monitor("proclist --json")).unwrap();
+    assert_eq!(procs[0]["name"], "fake_process");
+    assert_eq!(procs[0]["pid"], 1234);
+    assert_eq!(procs[0]["current"], true);
+
+    let taint: serde_json::Value = serde_json::from_str(&client.monitor("get_taint *0x1100 --json")).unwrap();
+    assert_eq!(taint["labels"], serde_json::json!([]));
+
+    assert!(client.monitor("addr 0x1010 --json").contains("ignoring --json"));
+}
+
+#[test]
+fn monitor_breakpoints() {
+    let mut client = Client::connect(Fake::default());
+
+    assert!(client.monitor("bp 0x1010").contains("Breakpoint at 0x1010"));
+    assert!(client.monitor("trace 0x1020 rax").contains("Tracepoint at 0x1020"));
+
+    let list = client.monitor("bp_list");
+    assert!(list.lines().any(|line| line.starts_with("0x1010") && line.contains("break")));
+    assert!(list.lines().any(|line| line.starts_with("0x1020") && line.contains("trace")));
+
+    assert!(client.monitor("bp_del 0x1010").contains("Deleted breakpoint at 0x1010"));
+    assert!(client.monitor("bp_del 0x1010").contains("No breakpoint at 0x1010"));
+    assert!(!client.monitor("bp_list").contains("0x1010"));
+}
+
+#[test]
+fn monitor_catchpoints() {
+    let mut client = Client::connect(Fake::default());
+
+    assert!(client.monitor("catch int80").contains("Catching exception 128"));
+    assert!(client.monitor("catch 14 proc").contains("in ASID 0x1000"));
+
+    let list = client.monitor("catch list");
+    assert!(list.contains("  128 (int80)\n"));
+    assert!(list.contains("(ASID 0x1000 only)"));
+
+    assert!(client.monitor("uncatch int80").contains("No longer catching exception 128"));
+    assert!(client.monitor("uncatch int80").contains("wasn't being caught"));
+
+    assert!(client.monitor("catch_switch on").contains("ASID 0x1000"));
+    assert!(client.monitor("catch_switch").contains("Stopping whenever ASID 0x1000"));
+    assert!(client.monitor("catch_switch off").contains("No longer stopping"));
+}
+
+#[test]
+fn monitor_trace_frames() {
+    let mut client = Client::connect(Fake::default());
+    assert!(client.monitor("tfind").contains("No trace frames collected"));
+
+    let fake = Fake {
+        trace_frames: vec![
+            TraceFrame {
+                pc: 0x1010,
+                instr_count: 4,
+                hit: 1,
+                values: vec![(String::from("rax"), Some(String::from("0x1")))],
+            },
+            TraceFrame {
+                pc: 0x1020,
+                instr_count: 8,
+                hit: 1,
+                values: vec![(String::from("rax"), None)],
+            },
+        ],
+        dropped_trace_frames: 3,
+        ..Default::default()
+    };
+    let mut client = Client::connect(fake);
+
+    let frames = client.monitor("tfind pc 0x1020");
+    assert!(frames.contains("#1"));
+    assert!(!frames.contains("#0"));
+    assert!(frames.contains("3 later frames weren't kept"));
+
+    assert!(client.monitor("tfind 5").contains("No matching trace frames (2 collected)"));
+    assert!(client.monitor("tclear").contains("Cleared all trace frames"));
+    assert!(client.monitor("tfind").contains("No trace frames collected"));
+}
+
+#[test]
+fn monitor_stepping() {
+    let mut client = Client::connect(Fake::default());
+
+    assert!(client.monitor("user_step").contains("stop in kernel code"));
+    assert!(client.monitor("user_step on").contains("skip over kernel code"));
+    assert!(client.monitor("user_step").contains("skip over kernel code"));
+    assert!(client.monitor("finish_kernel").contains("Already in user mode"));
+
+    let mut client = Client::connect(Fake { in_kernel: true, ..Default::default() });
+    assert!(client.monitor("finish_kernel").contains("until ASID 0x1000 returns to user mode"));
+}
+
+#[test]
+fn monitor_memory_view() {
+    let mut fake = Fake::default();
+    fake.processes.push(Process {
+        pid: 42,
+        ppid: 1,
+        asid: 0x2000,
+        create_time: 0,
+        name: String::from("other"),
+    });
+    let mut client = Client::connect(fake);
+
+    assert!(client.monitor("memview").contains("use the current process"));
+    assert!(client.monitor("memview pid 42").contains("other (PID 42, ASID 0x2000)"));
+    assert!(client.monitor("memview").contains("ASID 0x2000"));
+    assert!(client.monitor("memview reset").contains("current process again"));
+    assert!(client.monitor("memview pid 7").contains("No such process"));
+}
+
+#[test]
+fn monitor_json_mode() {
+    let mut client = Client::connect(Fake::default());
+
+    assert!(client.monitor("json on").contains("print JSON"));
+    assert!(client.monitor("meminfo").trim_start().starts_with('['));
+    assert!(client.monitor("json off").contains("print tables"));
+    assert!(!client.monitor("meminfo").trim_start().starts_with('['));
+}
+
+#[test]
+fn monitor_branch_history() {
+    let mut client = Client::connect(Fake::default());
+    assert!(client.monitor("branch_history").contains("No branches recorded"));
+
+    let fake = Fake {
+        branches: vec![
+            Branch { block_start: 0x1000, block_end: 0x1010, target: 0x1100 },
+            Branch { block_start: 0x1100, block_end: 0x1108, target: 0x1000 },
+        ],
+        ..Default::default()
+    };
+    let mut client = Client::connect(fake);
+
+    let branches = client.monitor("branch_history 1");
+    assert!(branches.contains("#0   0x1100..0x1108"));
+    assert!(!branches.contains("#1"));
+}
+
+#[test]
+fn monitor_grammar() {
+    let mut fake = Fake::default();
+    fake.regs.regs[7] = 0x1100;
+    let mut client = Client::connect(fake);
+
+    // Addresses relative to registers, and commands chained with ;
+    let output = client.monitor("taint *$rsp+0x10 1; get_taint *0x1110");
+    assert!(output.contains("tainted"), "{}", output);
+    assert!(output.contains("[1]"), "{}", output);
+
+    assert!(client.monitor("help bp").contains("Usage: bp ADDR"));
+    assert!(client.monitor("proc").contains("Did you mean: procinfo, proclist"));
+    assert!(client.monitor("check_taint xyz").contains("Registers: "));
+}
+
+#[test]
+fn console_output() {
+    let mut fake = Fake::default();
+    fake.output = b"hello from the guest\n".to_vec();
+    fake.breakpoints.insert(0x1008);
+    let mut client = Client::connect_with_acks(fake);
+
+    // Output arrives as O packets, acked like any other, before the stop reply
+    client.send("c");
+    let mut output = Vec::new();
+    let stop = loop {
+        let packet = client.receive();
+        match packet.strip_prefix('O') {
+            Some(text) => output.extend(decode_hex(text)),
+            None => break packet,
+        }
+    };
+    assert_eq!(output, b"hello from the guest\n");
+    assert!(stop.starts_with("T05"), "{}", stop);
+
+    // The stub carries on after the acks
+    assert_eq!(client.rip(), 0x1008);
+    assert_eq!(client.request("m1100,4"), "00000000");
+}
+
+#[test]
+fn console_output_packet() {
+    assert_eq!(crate::process_output::console_packet(b"hi\n"), "$O68690a#bd");
+}